@@ -0,0 +1,127 @@
+use crate::cli::PeakMode;
+use crate::loudness;
+use crate::sample_math::SampleMath;
+use crate::segment_layout::SegmentSlice;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Features of each processed segment, written as a JSON array
+pub struct Analysis {
+    segments: Vec<Features>,
+    scale: f64,
+    channels: usize,
+    peak_mode: PeakMode,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Features {
+    index: usize,
+    offset: u32,
+    length: usize,
+    /// Offset each channel's segment was read from
+    sources: Vec<u32>,
+    rms: f32,
+    peak: f32,
+    effects: Vec<&'static str>,
+}
+
+impl Analysis {
+    pub fn new(spec: hound::WavSpec, peak_mode: PeakMode) -> Analysis {
+        let scale = match spec.sample_format {
+            hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f64,
+            hound::SampleFormat::Float => 1.,
+        };
+
+        Analysis {
+            segments: vec![],
+            scale,
+            channels: spec.channels as usize,
+            peak_mode,
+        }
+    }
+
+    /// Measures the samples read for `slice`, `moved` standing for swapped or repeated
+    pub fn push<S: SampleMath>(&mut self, slice: &SegmentSlice, samples: &[S]) {
+        let segments = slice.segments();
+        let flags = [
+            (
+                "moved",
+                segments.iter().any(|s| s.offset() != slice.offset()),
+            ),
+            ("reverse", segments.iter().any(|s| s.reverse())),
+            ("silence", segments.iter().any(|s| s.silence())),
+            ("freeze", segments.iter().any(|s| s.freeze())),
+            ("granular", segments.iter().any(|s| s.granular())),
+            ("beat-repeat", segments.iter().any(|s| s.beat_repeat())),
+            ("widen", segments.iter().any(|s| s.invert())),
+            ("gain", segments.iter().any(|s| s.gain().is_some())),
+        ];
+        let samples: Vec<f64> = samples
+            .iter()
+            .map(|sample| sample.to_f64() / self.scale)
+            .collect();
+        let power =
+            samples.iter().map(|sample| sample.powi(2)).sum::<f64>() / samples.len().max(1) as f64;
+        let peak = loudness::peak(&samples, self.channels, self.peak_mode);
+
+        self.segments.push(Features {
+            index: self.segments.len(),
+            offset: slice.offset(),
+            length: slice.segment_len(),
+            sources: segments.iter().map(|segment| segment.offset()).collect(),
+            rms: power.sqrt() as f32,
+            peak: peak as f32,
+            effects: flags
+                .into_iter()
+                .filter(|&(_, applied)| applied)
+                .map(|(name, _)| name)
+                .collect(),
+        });
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path).context("when creating analysis file")?;
+        serde_json::to_writer(BufWriter::new(file), &self.segments)
+            .context("when writing analysis file")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment_layout::Segment;
+
+    #[test]
+    fn analysis_push() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut analysis = Analysis::new(spec, PeakMode::Sample);
+        let slice = SegmentSlice::new(
+            vec![Segment::new(4, true, false), Segment::new(2, false, false)],
+            2,
+            2,
+            0.,
+        );
+        analysis.push(&slice, &[16384i32, -16384, 16384, 16384]);
+
+        assert_eq!(
+            analysis.segments,
+            [Features {
+                index: 0,
+                offset: 2,
+                length: 2,
+                sources: vec![4, 2],
+                rms: 0.5,
+                peak: 0.5,
+                effects: vec!["moved", "reverse"],
+            }]
+        );
+    }
+}
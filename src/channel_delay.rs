@@ -0,0 +1,67 @@
+use crate::sample_math::SampleMath;
+use std::collections::VecDeque;
+
+/// Delays each channel by its own number of samples
+pub struct ChannelDelay {
+    channels: Vec<VecDeque<f64>>,
+}
+
+impl ChannelDelay {
+    pub fn new(delays: &[u32]) -> ChannelDelay {
+        let channels = delays
+            .iter()
+            .map(|&delay| VecDeque::from(vec![0.; delay as usize]))
+            .collect();
+
+        ChannelDelay { channels }
+    }
+
+    pub fn process<S: SampleMath>(&mut self, samples: &mut [S]) {
+        for frame in samples.chunks_mut(self.channels.len()) {
+            for (sample, buffer) in frame.iter_mut().zip(&mut self.channels) {
+                if let Some(delayed) = buffer.pop_front() {
+                    buffer.push_back(sample.to_f64());
+                    *sample = S::from_f64(delayed);
+                }
+            }
+        }
+    }
+
+    /// Remaining delayed samples, padded with silence to the longest delay
+    pub fn flush<S: SampleMath>(&mut self) -> Vec<S> {
+        let frames = self.channels.iter().map(VecDeque::len).max().unwrap_or(0);
+        let mut samples = Vec::with_capacity(frames * self.channels.len());
+        for _ in 0..frames {
+            for buffer in &mut self.channels {
+                samples.push(S::from_f64(buffer.pop_front().unwrap_or(0.)));
+            }
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_delay_shifts() {
+        let mut delay = ChannelDelay::new(&[0, 1, 3]);
+        let mut samples: Vec<i32> = (1..=4).flat_map(|i| [i, 10 * i, 100 * i]).collect();
+        delay.process(&mut samples);
+        samples.extend(delay.flush::<i32>());
+
+        assert_eq!(
+            samples,
+            [
+                1, 0, 0, //
+                2, 10, 0, //
+                3, 20, 0, //
+                4, 30, 100, //
+                0, 40, 200, //
+                0, 0, 300, //
+                0, 0, 400,
+            ]
+        );
+    }
+}
@@ -1,5 +1,11 @@
+use crate::segment_layout::SegmentLayout;
+use crate::wav_reader::{ReadMode, WavConfig};
 pub use clap::Parser;
+use clap::ValueEnum;
+use serde::Serialize;
 use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 use yansi::Paint;
 
 #[derive(Debug, Parser)]
@@ -10,21 +16,49 @@ use yansi::Paint;
     after_help = format!("{}\n  Process `in.wav`, dividing it into segments with a length of a 1/32 note in 120 BPM and output\n  \
         result to `processed.wav` with 10% chance of repeating a segment up to 20 times.\n  \
         Use defaults for other options.\n  \
-        $ wavglitch in.wav -o processed.wav -t 120 -l 1/32 -p 0.1 -n 20", "Example:".bold().underline()),
+        $ wavglitch in.wav -o processed.wav -t 120 -l 1/32 -p 0.1 -n 20\n\n  \
+        Run with `--examples` to see an example for each effect.", "Example:".bold().underline()),
     arg_required_else_help = true,
     args_override_self = true
 )]
 pub struct Cli {
     /// Input WAV file path
-    #[arg(value_name = "input")]
-    input: PathBuf,
+    #[arg(value_name = "input", required_unless_present = "examples")]
+    input: Option<PathBuf>,
+    /// WAV files with the same spec to append to the input and glitch with it as one stream
+    #[arg(long = "concat", value_name = "paths", num_args = 1..)]
+    concat: Vec<PathBuf>,
     /// Output WAV file path (defaults to `out.wav`)
     #[arg(short = 'o', long = "output", value_name = "path")]
     output: Option<PathBuf>,
+    /// Output directory, created if needed, to write `<input stem>-glitch.wav` into
+    #[arg(long = "output-dir", value_name = "dir", conflicts_with = "output")]
+    output_dir: Option<PathBuf>,
+    /// Append the UTC start time to the output file name and write it as the INFO creation date (defaults to false)
+    #[arg(long = "timestamp")]
+    timestamp: bool,
+    /// Preset of option values, overridden by options given explicitly
+    #[arg(long = "preset", value_name = "name")]
+    preset: Option<Preset>,
+    /// Complexity of the glitching from 0 to 10, setting effect options not given explicitly
+    #[arg(long = "complexity", value_name = "level", value_parser = clap::value_parser!(u8).range(0..=10), conflicts_with = "preset")]
+    complexity: Option<u8>,
+    /// Seed of the random generator in decimal or 0x-prefixed hex, to reproduce an output (defaults to a random seed, which is printed)
+    #[arg(short = 'S', long = "seed", value_name = "n", value_parser = Cli::seed_parser)]
+    seed: Option<u64>,
+    /// Format of printed seeds, `dec` or `hex` (defaults to dec)
+    #[arg(long = "seed-format", value_name = "format")]
+    seed_format: Option<SeedFormat>,
+    /// Rebuild a layout that would leave the input unchanged, failing if that keeps happening (defaults to false)
+    #[arg(long = "ensure-change")]
+    ensure_change: bool,
     /// Tempo, 1.0 to 4095.0 (defaults to 100.0)
     #[arg(short = 't', long = "tempo", value_name = "value", value_parser = Cli::tempo_parser)]
     tempo: Option<f64>,
-    /// Length of a single segment, relative note value in x/y format (defaults to 1/16)
+    /// Estimate the tempo from the onsets of the input when it is not given (defaults to false)
+    #[arg(long = "auto-tempo")]
+    auto_tempo: bool,
+    /// Length of a single segment, relative note value in x/y format, followed by `.` for dotted or `t` for triplet (defaults to 1/16)
     #[arg(short = 'l', long = "length", value_name = "value", value_parser = Cli::segment_parser)]
     segment_length: Option<f64>,
     /// Probability of silencing segment, 0.0 to 1.0 (defaults to 0.0)
@@ -39,18 +73,447 @@ pub struct Cli {
     /// Probability of repeating segment, 0.0 to 1.0 (defaults to 0.0)
     #[arg(short = 'p', long = "repeat", value_name = "prob", value_parser = Cli::probability_parser)]
     prob_repeat: Option<f64>,
+    /// Probability of freezing segment, holding the spectrum of its start with the `fft` feature or looping grains of it without, 0.0 to 1.0 (defaults to 0.0)
+    #[arg(long = "freeze", value_name = "prob", value_parser = Cli::probability_parser)]
+    prob_freeze: Option<f64>,
+    /// Probability of inverting second channel of segment, 0.0 to 1.0 (defaults to 0.0)
+    #[arg(long = "widen", value_name = "prob", value_parser = Cli::probability_parser)]
+    prob_widen: Option<f64>,
+    /// Probability of rebuilding segment from a cloud of grains, 0.0 to 1.0 (defaults to 0.0)
+    #[arg(long = "granular", value_name = "prob", value_parser = Cli::probability_parser)]
+    prob_granular: Option<f64>,
+    /// Probability of playing the subdivisions of segment in shuffled order, 0.0 to 1.0 (defaults to 0.0)
+    #[arg(long = "beat-repeat", value_name = "prob", value_parser = Cli::probability_parser)]
+    prob_beat_repeat: Option<f64>,
+    /// Probability of scaling the volume of segment by the gain amount, 0.0 to 1.0 (defaults to 0.0)
+    #[arg(short = 'g', long = "gain", value_name = "prob", value_parser = Cli::probability_parser)]
+    prob_gain: Option<f64>,
+    /// Multiplier of the volume of a gained segment, 0.0 to 4.0 (defaults to 0.5)
+    #[arg(long = "gain-amount", value_name = "amount", value_parser = Cli::gain_parser)]
+    gain_amount: Option<f64>,
+    /// Probabilities of several effects in effect=prob,... format, overridden by the effects' own options
+    #[arg(long = "effects", value_name = "effect=prob,...", value_delimiter = ',', value_parser = Cli::effect_parser)]
+    effects: Vec<(Effect, f64)>,
+    /// Probability of an effect ramping from start to end through the input in effect=start:end format (repeatable)
+    #[arg(long = "envelope", value_name = "effect=start:end", value_parser = Cli::envelope_parser)]
+    envelope: Vec<(Effect, (f64, f64))>,
+    /// Salt of the random stream of an effect in effect=salt format, changing only the segments it picks, except with --budget (repeatable)
+    #[arg(long = "effect-seed-salt", value_name = "effect=salt", value_parser = Cli::salt_parser)]
+    effect_seed_salt: Vec<(Effect, u64)>,
+    /// Shape scaling effect probabilities by position through the input (defaults to flat)
+    #[arg(long = "position-weight", value_name = "shape")]
+    position_weight: Option<PositionWeight>,
+    /// File of multipliers, 0.0 to 1.0, for effect probabilities through the input, one per segment or fewer to interpolate
+    #[arg(long = "prob-curve-file", value_name = "path")]
+    prob_curve_file: Option<PathBuf>,
+    /// WAV file path whose level scales effect probabilities, glitching more where it is louder
+    #[arg(
+        long = "sidechain",
+        value_name = "path",
+        conflicts_with = "prob_curve_file"
+    )]
+    sidechain: Option<PathBuf>,
+    /// File of effect probabilities for each channel, a line per channel with a value per effect in the order silence, swap, reverse, repeat, freeze, granular, widen, beat-repeat, gain
+    #[arg(long = "prob-matrix", value_name = "path")]
+    prob_matrix: Option<PathBuf>,
+    /// Grain length in milliseconds and number of overlapping grains in ms:density format (defaults to 50:4)
+    #[arg(long = "grains", value_name = "ms:density", value_parser = Cli::grains_parser)]
+    grains: Option<(f64, f64)>,
+    /// Number of subdivisions of a beat repeated segment, 2 to 64 (defaults to 4)
+    #[arg(long = "divisions", value_name = "k", value_parser = clap::value_parser!(u8).range(2..=64))]
+    divisions: Option<u8>,
+    /// Length in milliseconds of fades into and out of silenced segments (defaults to 0.0)
+    #[arg(long = "silence-fade", value_name = "ms", value_parser = Cli::milliseconds_parser)]
+    silence_fade: Option<f64>,
+    /// Ramp silenced segments from and back to the input over a millisecond instead of cutting to zero (defaults to false)
+    #[arg(long = "silence-ramp")]
+    silence_ramp: bool,
+    /// Length in milliseconds of crossfades between adjacent segments, blending out what each segment would play next (defaults to 0.0)
+    #[arg(long = "crossfade", value_name = "ms", value_parser = Cli::milliseconds_parser)]
+    crossfade: Option<f64>,
+    /// Shape of the fades of silenced and reversed segments (defaults to linear)
+    #[arg(long = "fade-curve", value_name = "curve")]
+    fade_curve: Option<FadeCurve>,
     /// Maximal swap range, 1 to 65535 (defaults to 8)
     #[arg(short = 'a', long = "range", value_name = "max", value_parser = clap::value_parser!(u16).range(1..))]
     max_swap: Option<u16>,
-    /// Maximal number of repetitions, 1 to 65535 (defaults to 8)
+    /// Maximal number of extra copies of a repeated segment, 1 to 65535 (defaults to 8)
     #[arg(short = 'n', long = "number", value_name = "max", value_parser = clap::value_parser!(u16).range(1..))]
     max_repeat: Option<u16>,
+    /// Semitones, -24.0 to 24.0, to pitch each successive copy of a repeated segment by (defaults to 0.0)
+    #[arg(long = "repeat-pitch", value_name = "semitones", allow_negative_numbers = true, value_parser = Cli::semitones_parser)]
+    repeat_pitch: Option<f64>,
+    /// Number of random segments that each get exactly one effect, picked by probability, instead of rolling every segment
+    #[arg(long = "budget", value_name = "n")]
+    budget: Option<usize>,
+    /// Minimal number of clean segments between glitched ones (defaults to 0)
+    #[arg(long = "min-gap", value_name = "n")]
+    min_gap: Option<usize>,
     /// Process each channel separately (defaults to false)
     #[arg(short = 'c', long = "channels")]
     each_channel_separately: bool,
+    /// Fade reversed segments in and out to soften their edges (defaults to false)
+    #[arg(long = "reverse-window")]
+    reverse_window: bool,
+    /// RMS level in dBFS, -96.0 to 0.0, that a segment must exceed to be reversed
+    #[arg(long = "reverse-only-above", value_name = "dBFS", allow_negative_numbers = true, value_parser = Cli::dbfs_parser)]
+    reverse_above: Option<f64>,
+    /// Glitch only the side of a stereo input, keeping its mid intact (defaults to false)
+    #[arg(long = "mid-glitch")]
+    mid_glitch: bool,
+    /// Glitch only segments holding the start of a beat at the tempo (defaults to false)
+    #[arg(long = "on-beats")]
+    on_beats: bool,
+    /// Remove DC offset from each segment (defaults to false)
+    #[arg(long = "segment-dc-block")]
+    segment_dc_block: bool,
+    /// Mix of original and processed audio, 0.0 to 1.0 (defaults to 1.0)
+    #[arg(long = "dry-wet", value_name = "mix", value_parser = Cli::probability_parser)]
+    dry_wet: Option<f64>,
+    /// Level of processed audio summed on top of the original, 0.0 to 1.0
+    #[arg(long = "underlay", value_name = "level", value_parser = Cli::probability_parser)]
+    underlay: Option<f64>,
+    /// Amount of stereo decorrelation applied to the output, 0.0 to 1.0 (defaults to 0.0)
+    #[arg(long = "decorrelate", value_name = "amount", value_parser = Cli::probability_parser)]
+    decorrelate: Option<f64>,
+    /// Delay in samples of each output channel, up to 10 seconds, e.g. 0,64,128
+    #[arg(long = "channel-delays", value_name = "samples", value_delimiter = ',')]
+    channel_delays: Vec<u32>,
+    /// Ratio and threshold in dBFS of the compressor applied to the output in ratio:threshold format
+    #[arg(long = "glue", value_name = "ratio:threshold", value_parser = Cli::glue_parser)]
+    glue: Option<(f64, f64)>,
+    /// Strength of loudness matching between segments, 0.0 to 1.0 (defaults to 0.0)
+    #[arg(long = "level-match", value_name = "strength", value_parser = Cli::probability_parser)]
+    level_match: Option<f64>,
+    /// Offset of the segment grid in samples (defaults to 0)
+    #[arg(long = "grid-offset", value_name = "samples")]
+    grid_offset: Option<u32>,
+    /// Groups of input channels to average into single channels before processing, e.g. 0,1:2,3
+    #[arg(long = "merge", value_name = "groups", value_delimiter = ':', value_parser = Cli::group_parser)]
+    merge: Vec<Vec<u16>>,
+    /// Mixing matrix file path, with a row of input channel weights for each channel to process
+    #[arg(long = "matrix", value_name = "path", conflicts_with = "merge")]
+    matrix: Option<PathBuf>,
+    /// Channel to output in each output channel, e.g. 1,0 to swap stereo channels
+    #[arg(long = "channel-order", value_name = "list", value_delimiter = ',')]
+    channel_order: Vec<u16>,
+    /// Number of channels to cyclically rotate the output by (defaults to 0)
+    #[arg(long = "rotate-channels", value_name = "n")]
+    rotate_channels: Option<u16>,
+    /// Position in seconds to start processing from (defaults to 0.0)
+    #[arg(long = "start", value_name = "seconds", value_parser = Cli::seconds_parser)]
+    start: Option<f64>,
+    /// Length in seconds of the unprocessed ending (defaults to 0.0)
+    #[arg(long = "clean-tail", value_name = "seconds", value_parser = Cli::seconds_parser)]
+    clean_tail: Option<f64>,
+    /// Keep writing after the end of input until effect tails decay (defaults to false)
+    #[arg(long = "render-tail")]
+    render_tail: bool,
+    /// Start segments at detected transients instead of a fixed grid (defaults to false)
+    #[arg(long = "transient-segments")]
+    transient_segments: bool,
+    /// Options of a second glitch chain over the options of this one, rendered from the input and mixed with it at half gain each before the output stages, e.g. "-r 0.5 -t 140"
+    #[arg(
+        long = "parallel-chain",
+        value_name = "options",
+        allow_hyphen_values = true
+    )]
+    parallel_chain: Option<String>,
+    /// Layout JSON file path, loaded if it exists and saved after building otherwise
+    #[arg(long = "segment-layout-cache", value_name = "path")]
+    layout_cache: Option<PathBuf>,
+    /// Size of the input read buffer in bytes (defaults to 8192)
+    #[arg(long = "read-buffer-size", value_name = "bytes", value_parser = clap::value_parser!(u32).range(1..))]
+    read_buffer_size: Option<u32>,
+    /// Maximal number of input channels, 1 to 65535 (defaults to 32)
+    #[arg(long = "max-channels", value_name = "n", value_parser = clap::value_parser!(u16).range(1..))]
+    max_channels: Option<u16>,
+    /// Memory-map the input instead of reading it through a buffer (defaults to false)
+    #[cfg(feature = "mmap")]
+    #[arg(long = "mmap", conflicts_with = "read_buffer_size")]
+    mmap: bool,
+    /// Do not print defaults and progress (defaults to false)
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+    /// Ask on a terminal whether to overwrite an existing output (defaults to false)
+    #[arg(long = "interactive")]
+    interactive: bool,
+    /// Overwrite an existing output without asking (defaults to false)
+    #[arg(short = 'f', long = "force", conflicts_with = "append")]
+    force: bool,
+    /// Treat flags that would have no effect as errors (defaults to false)
+    #[arg(long = "strict")]
+    strict: bool,
+    /// Check that the input can be read without processing it (defaults to false)
+    #[arg(long = "validate-only")]
+    validate_only: bool,
+    /// Print an example command line for each effect and exit
+    #[arg(long = "examples")]
+    examples: bool,
+    /// Process without writing output and report throughput (defaults to false)
+    #[arg(long = "benchmark")]
+    benchmark: bool,
+    /// Print a SHA-256 checksum of the output samples (defaults to false)
+    #[arg(long = "checksum")]
+    checksum: bool,
+    /// Print the output file encoded as base64, for sharing short clips, moving other reports to stderr (defaults to false)
+    #[arg(long = "base64")]
+    base64: bool,
+    /// Print the number of input samples at full scale, warning if there are any (defaults to false)
+    #[arg(long = "check-input-clipping")]
+    check_input_clipping: bool,
+    /// Print the DC offset of each output channel, warning if it is above -40 dBFS (defaults to false)
+    #[arg(long = "dc-report")]
+    dc_report: bool,
+    /// Print estimates of peak memory and processing time before processing (defaults to false)
+    #[arg(long = "resource-report")]
+    resource_report: bool,
+    /// Print the fraction of the timeline each effect covers (defaults to false)
+    #[arg(long = "coverage")]
+    coverage: bool,
+    /// Build layouts for this many seeds, print the average fraction of segments that differ between them and exit
+    #[arg(long = "seed-variance", value_name = "count", value_parser = clap::value_parser!(u16).range(2..))]
+    seed_variance: Option<u16>,
+    /// Render the first secs seconds for each seed from start to end, inclusive, into one output with half a second of silence between them
+    #[arg(
+        long = "seed-sweep",
+        value_name = "start:end:secs",
+        value_parser = Cli::seed_sweep_parser,
+        conflicts_with_all = ["seed", "seed_variance", "layout_cache", "parallel_chain", "append"]
+    )]
+    seed_sweep: Option<(u64, u64, f64)>,
+    /// Handling of integer samples exceeding the bit depth, `saturate`, `wrap` or `panic` (defaults to saturate)
+    #[arg(long = "int-math", value_name = "mode")]
+    int_math: Option<IntMath>,
+    /// Integrated loudness in LUFS to bring the output to, -70.0 to 0.0, with peaks limited to -1 dBFS
+    #[arg(long = "lufs", value_name = "target", allow_negative_numbers = true, value_parser = Cli::lufs_parser)]
+    lufs: Option<f64>,
+    /// Measurement of peaks for limiting and analysis, `sample` or `true` (defaults to sample)
+    #[arg(long = "peak-mode", value_name = "mode")]
+    peak_mode: Option<PeakMode>,
+    /// Write the output at the smallest integer bit depth that holds it without loss (defaults to false)
+    #[arg(long = "output-bit-depth-auto")]
+    auto_bit_depth: bool,
+    /// Write the output as 32-bit float whatever the input format (defaults to false)
+    #[arg(long = "float", conflicts_with = "auto_bit_depth")]
+    float: bool,
+    /// Remove silence at the start and the end of the output (defaults to false)
+    #[arg(long = "trim-output-silence")]
+    trim_output_silence: bool,
+    /// Maximal size of the sample data of the output in megabytes, counting existing data when appending
+    #[arg(long = "max-output-size", value_name = "MB", value_parser = Cli::size_parser)]
+    max_output_size: Option<f64>,
+    /// Waveform overview (peaks) JSON file path
+    #[arg(long = "peaks", value_name = "path")]
+    peaks: Option<PathBuf>,
+    /// Per-segment features (offset, length, RMS, peak and effects) JSON file path
+    #[arg(long = "analysis", value_name = "path")]
+    analysis: Option<PathBuf>,
+    /// Run log file path, appended a JSON line with the input, output, options and warnings of each run
+    #[arg(long = "log", value_name = "path")]
+    log: Option<PathBuf>,
+    /// Path to also write the input reassembled from the segment grid without effects to
+    #[arg(long = "write-intermediate", value_name = "path")]
+    intermediate: Option<PathBuf>,
+    /// Segment layout timeline SVG file path
+    #[arg(long = "layout-svg", value_name = "path")]
+    layout_svg: Option<PathBuf>,
+    /// Metadata to write into the output INFO chunk, title/artist/comment/date/genre/software or a four-letter ID (repeatable)
+    #[arg(long = "meta", value_name = "key=value", value_parser = Cli::meta_parser)]
+    meta: Vec<([u8; 4], String)>,
+    /// Loop region written as a smpl chunk, start and inclusive end sample frame in start:end format, failing without an output if the end is past it
+    #[arg(long = "loop-points", value_name = "start:end", value_parser = Cli::loop_parser)]
+    loop_points: Option<(u32, u32)>,
+    /// Append to the output file if it exists instead of failing, which must have the same spec and no metadata (defaults to false)
+    #[arg(long = "append", conflicts_with_all = ["meta", "loop_points", "interactive"])]
+    append: bool,
+    /// Impulse response WAV file path to convolve the output with
+    #[cfg(feature = "fft")]
+    #[arg(long = "reverb", value_name = "path")]
+    reverb: Option<PathBuf>,
+    /// Tempo estimated from the input with `--auto-tempo`
+    #[arg(skip)]
+    detected_tempo: OnceLock<f64>,
+    /// Seconds since the Unix epoch when first asked for, so that every use agrees
+    #[arg(skip)]
+    started: OnceLock<u64>,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum Preset {
+    /// Short segments repeated often
+    Stutter,
+    /// Long, slowed-down feeling segments that are reversed and frozen
+    Vaporwave,
+    /// Heavy swapping, repeating and dropouts
+    Breakcore,
+    /// Occasional glitches mixed with the original
+    Subtle,
+}
+
+impl Preset {
+    fn config(self) -> CliConfig {
+        match self {
+            Preset::Stutter => CliConfig {
+                segment_length: 1. / 32.,
+                prob_repeat: 0.3,
+                max_repeat: 4,
+                ..Default::default()
+            },
+            Preset::Vaporwave => CliConfig {
+                segment_length: 0.25,
+                prob_reverse: 0.25,
+                prob_freeze: 0.15,
+                prob_swap: 0.1,
+                max_swap: 2,
+                ..Default::default()
+            },
+            Preset::Breakcore => CliConfig {
+                segment_length: 0.0625,
+                prob_silence: 0.1,
+                prob_swap: 0.4,
+                prob_reverse: 0.15,
+                prob_repeat: 0.25,
+                max_swap: 4,
+                ..Default::default()
+            },
+            Preset::Subtle => CliConfig {
+                prob_swap: 0.05,
+                prob_reverse: 0.05,
+                prob_repeat: 0.05,
+                max_swap: 2,
+                max_repeat: 2,
+                dry_wet: 0.5,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, ValueEnum)]
+pub enum Effect {
+    Silence,
+    Swap,
+    Reverse,
+    Repeat,
+    Freeze,
+    Granular,
+    Widen,
+    BeatRepeat,
+    Gain,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PositionWeight {
+    /// The same everywhere
+    #[default]
+    Flat,
+    /// Highest in the middle, calm at the start and end
+    Bell,
+    /// Highest at the start and end, calm in the middle
+    Edges,
+    /// Rising from the start to the end
+    RampUp,
+    /// Falling from the start to the end
+    RampDown,
+}
+
+impl PositionWeight {
+    /// Width of the bell as a standard deviation of the position
+    const BELL_WIDTH: f64 = 0.2;
+
+    /// Multiplier at `position` from 0.0 to 1.0 through the input
+    pub fn at(self, position: f64) -> f64 {
+        let bell = || (-0.5 * ((position - 0.5) / Self::BELL_WIDTH).powi(2)).exp();
+        match self {
+            PositionWeight::Flat => 1.,
+            PositionWeight::Bell => bell(),
+            PositionWeight::Edges => 1. - bell(),
+            PositionWeight::RampUp => position,
+            PositionWeight::RampDown => 1. - position,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FadeCurve {
+    /// Gain rising in a straight line
+    #[default]
+    Linear,
+    /// Constant energy when crossing another fade
+    EqualPower,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, ValueEnum)]
+pub enum PeakMode {
+    /// Highest absolute sample value
+    #[default]
+    Sample,
+    /// Highest value of the signal oversampled four times, catching peaks between samples
+    True,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, ValueEnum)]
+pub enum SeedFormat {
+    /// Decimal
+    #[default]
+    Dec,
+    /// Hexadecimal with a 0x prefix, as -S accepts it
+    Hex,
+}
+
+impl SeedFormat {
+    pub fn format(self, seed: u64) -> String {
+        match self {
+            Self::Dec => seed.to_string(),
+            Self::Hex => format!("{seed:#x}"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IntMath {
+    /// Clamp to the nearest value that fits
+    #[default]
+    Saturate,
+    /// Wrap around as two's complement arithmetic does
+    Wrap,
+    /// Stop with an error
+    Panic,
+}
+
+/// Probability ramps from start to end over the segments, one per effect
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Envelopes([Option<(f64, f64)>; 9]);
+
+impl Envelopes {
+    pub fn get(&self, effect: Effect) -> Option<(f64, f64)> {
+        self.0[effect as usize]
+    }
+
+    pub fn set(&mut self, effect: Effect, envelope: (f64, f64)) {
+        self.0[effect as usize] = Some(envelope);
+    }
+}
+
+/// Salts of the random streams, one per effect
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Salts([u64; 9]);
+
+impl Salts {
+    pub fn get(&self, effect: Effect) -> u64 {
+        self.0[effect as usize]
+    }
+
+    pub fn set(&mut self, effect: Effect, salt: u64) {
+        self.0[effect as usize] = salt;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
 pub struct CliConfig {
     pub tempo: f64,
     pub segment_length: f64,
@@ -58,67 +521,786 @@ pub struct CliConfig {
     pub prob_swap: f64,
     pub prob_reverse: f64,
     pub prob_repeat: f64,
+    pub prob_freeze: f64,
+    pub prob_widen: f64,
+    pub prob_granular: f64,
+    pub prob_beat_repeat: f64,
+    pub prob_gain: f64,
+    pub gain_amount: f64,
+    pub envelopes: Envelopes,
+    pub salts: Salts,
+    pub position_weight: PositionWeight,
+    pub grain_len: f64,
+    pub grain_density: f64,
+    pub divisions: u8,
+    pub silence_fade: f64,
+    pub silence_ramp: bool,
+    pub crossfade: f64,
+    pub fade_curve: FadeCurve,
     pub max_swap: u16,
     pub max_repeat: u16,
+    pub repeat_pitch: f64,
+    pub budget: Option<usize>,
+    pub min_gap: usize,
     pub each_channel_separately: bool,
+    pub reverse_window: bool,
+    pub reverse_above: Option<f64>,
+    pub mid_glitch: bool,
+    pub on_beats: bool,
+    pub segment_dc_block: bool,
+    pub dry_wet: f64,
+    pub underlay: Option<f64>,
+    pub decorrelate: f64,
+    pub glue_ratio: f64,
+    pub glue_threshold: f64,
+    pub level_match: f64,
+    pub grid_offset: u32,
+    pub rotate_channels: u16,
+    pub start: f64,
+    pub clean_tail: f64,
+    pub render_tail: bool,
+    pub int_math: IntMath,
+    pub quiet: bool,
+}
+
+impl Default for CliConfig {
+    fn default() -> CliConfig {
+        CliConfig {
+            tempo: 100.,
+            segment_length: 0.0625,
+            prob_silence: 0.,
+            prob_swap: 0.,
+            prob_reverse: 0.,
+            prob_repeat: 0.,
+            prob_freeze: 0.,
+            prob_widen: 0.,
+            prob_granular: 0.,
+            prob_beat_repeat: 0.,
+            prob_gain: 0.,
+            gain_amount: 0.5,
+            envelopes: Envelopes::default(),
+            salts: Salts::default(),
+            position_weight: PositionWeight::Flat,
+            grain_len: 0.05,
+            grain_density: 4.,
+            divisions: 4,
+            silence_fade: 0.,
+            silence_ramp: false,
+            crossfade: 0.,
+            fade_curve: FadeCurve::Linear,
+            max_swap: 8,
+            max_repeat: 8,
+            repeat_pitch: 0.,
+            budget: None,
+            min_gap: 0,
+            each_channel_separately: false,
+            reverse_window: false,
+            reverse_above: None,
+            mid_glitch: false,
+            on_beats: false,
+            segment_dc_block: false,
+            dry_wet: 1.,
+            underlay: None,
+            decorrelate: 0.,
+            glue_ratio: 1.,
+            glue_threshold: 0.,
+            level_match: 0.,
+            grid_offset: 0,
+            rotate_channels: 0,
+            start: 0.,
+            clean_tail: 0.,
+            render_tail: false,
+            int_math: IntMath::Saturate,
+            quiet: false,
+        }
+    }
+}
+
+impl CliConfig {
+    /// Probability of `effect` at `position` from 0.0 to 1.0 through the segments
+    pub fn probability(&self, effect: Effect, position: f64) -> f64 {
+        let probability = match (self.envelopes.get(effect), effect) {
+            (Some((start, end)), _) => start + (end - start) * position,
+            (None, Effect::Silence) => self.prob_silence,
+            (None, Effect::Swap) => self.prob_swap,
+            (None, Effect::Reverse) => self.prob_reverse,
+            (None, Effect::Repeat) => self.prob_repeat,
+            (None, Effect::Freeze) => self.prob_freeze,
+            (None, Effect::Granular) => self.prob_granular,
+            (None, Effect::Widen) => self.prob_widen,
+            (None, Effect::BeatRepeat) => self.prob_beat_repeat,
+            (None, Effect::Gain) => self.prob_gain,
+        };
+        probability * self.position_weight.at(position)
+    }
+
+    /// Copy with the probability of `effect` set to `probability`
+    pub fn with_probability(mut self, effect: Effect, probability: f64) -> CliConfig {
+        let field = match effect {
+            Effect::Silence => &mut self.prob_silence,
+            Effect::Swap => &mut self.prob_swap,
+            Effect::Reverse => &mut self.prob_reverse,
+            Effect::Repeat => &mut self.prob_repeat,
+            Effect::Freeze => &mut self.prob_freeze,
+            Effect::Granular => &mut self.prob_granular,
+            Effect::Widen => &mut self.prob_widen,
+            Effect::BeatRepeat => &mut self.prob_beat_repeat,
+            Effect::Gain => &mut self.prob_gain,
+        };
+        *field = probability;
+        self
+    }
 }
 
 impl Cli {
+    /// Options that a parallel chain can set, those shaping its layout and how its segments
+    /// are read, as opposed to the input, output, timeline and output stages it shares with
+    /// the main chain
+    const CHAIN_OPTIONS: [&'static str; 39] = [
+        "tempo",
+        "segment_length",
+        "prob_silence",
+        "prob_swap",
+        "prob_reverse",
+        "prob_repeat",
+        "prob_freeze",
+        "prob_widen",
+        "prob_granular",
+        "prob_beat_repeat",
+        "prob_gain",
+        "gain_amount",
+        "effects",
+        "envelope",
+        "effect_seed_salt",
+        "position_weight",
+        "grains",
+        "divisions",
+        "silence_fade",
+        "silence_ramp",
+        "crossfade",
+        "fade_curve",
+        "max_swap",
+        "max_repeat",
+        "repeat_pitch",
+        "budget",
+        "min_gap",
+        "each_channel_separately",
+        "reverse_window",
+        "reverse_above",
+        "mid_glitch",
+        "on_beats",
+        "segment_dc_block",
+        "dry_wet",
+        "underlay",
+        "level_match",
+        "grid_offset",
+        "rotate_channels",
+        "clean_tail",
+    ];
+
     pub fn input(&self) -> PathBuf {
-        self.input.clone()
+        self.input.clone().unwrap_or_default()
+    }
+
+    pub fn concat(&self) -> Vec<PathBuf> {
+        self.concat.clone()
     }
 
     pub fn output(&self) -> PathBuf {
-        self.output.clone().unwrap_or("out.wav".into())
+        let output: PathBuf = match (&self.output, &self.output_dir) {
+            (Some(output), _) => output.clone(),
+            (None, Some(dir)) => {
+                let mut name = self.input().file_stem().unwrap_or_default().to_os_string();
+                name.push("-glitch.wav");
+                dir.join(name)
+            }
+            (None, None) => "out.wav".into(),
+        };
+        if !self.timestamp {
+            return output;
+        }
+
+        let mut name = output.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!("-{}", Self::timestamp(self.started())));
+        if let Some(extension) = output.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        output.with_file_name(name)
+    }
+
+    /// UTC start time of the run in ISO 8601 basic format
+    pub fn start_time(&self) -> String {
+        Self::timestamp(self.started())
+    }
+
+    fn started(&self) -> u64 {
+        *self.started.get_or_init(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        })
+    }
+
+    /// UTC date and time of `seconds` since the Unix epoch
+    fn utc(seconds: u64) -> [u64; 6] {
+        // Civil date from day count, after Howard Hinnant's `civil_from_days`
+        let days = seconds / 86400 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as u64;
+        let time = seconds % 86400;
+
+        [year, month, day, time / 3600, time / 60 % 60, time % 60]
+    }
+
+    /// ISO 8601 basic format of `seconds`, without colons so that it fits in a file name
+    fn timestamp(seconds: u64) -> String {
+        let [year, month, day, hour, minute, second] = Self::utc(seconds);
+        format!("{year:04}{month:02}{day:02}T{hour:02}{minute:02}{second:02}Z")
+    }
+
+    /// Probability of `effect` given by its own option or else by `--effects`
+    fn probability(&self, effect: Effect) -> Option<f64> {
+        let probability = match effect {
+            Effect::Silence => self.prob_silence,
+            Effect::Swap => self.prob_swap,
+            Effect::Reverse => self.prob_reverse,
+            Effect::Repeat => self.prob_repeat,
+            Effect::Freeze => self.prob_freeze,
+            Effect::Granular => self.prob_granular,
+            Effect::Widen => self.prob_widen,
+            Effect::BeatRepeat => self.prob_beat_repeat,
+            Effect::Gain => self.prob_gain,
+        };
+        probability.or_else(|| {
+            self.effects
+                .iter()
+                .rev()
+                .find(|&&(other, _)| other == effect)
+                .map(|&(_, probability)| probability)
+        })
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn seed_format(&self) -> SeedFormat {
+        self.seed_format.unwrap_or_default()
+    }
+
+    pub fn ensure_change(&self) -> bool {
+        self.ensure_change
+    }
+
+    pub fn output_dir(&self) -> Option<PathBuf> {
+        self.output_dir.clone()
     }
 
     pub fn config(&self) -> CliConfig {
+        let base = match (self.preset, self.complexity) {
+            (Some(preset), _) => preset.config(),
+            (None, Some(level)) => Self::complexity_config(level),
+            (None, None) => CliConfig::default(),
+        };
+        self.config_over(base)
+    }
+
+    /// Options given on the command line, falling back to `base` for the others
+    fn config_over(&self, base: CliConfig) -> CliConfig {
+        CliConfig {
+            tempo: self
+                .tempo
+                .or(self.detected_tempo.get().copied())
+                .unwrap_or(base.tempo),
+            segment_length: self.segment_length.unwrap_or(base.segment_length),
+            prob_silence: self
+                .probability(Effect::Silence)
+                .unwrap_or(base.prob_silence),
+            prob_swap: self.probability(Effect::Swap).unwrap_or(base.prob_swap),
+            prob_reverse: self
+                .probability(Effect::Reverse)
+                .unwrap_or(base.prob_reverse),
+            prob_repeat: self.probability(Effect::Repeat).unwrap_or(base.prob_repeat),
+            prob_freeze: self.probability(Effect::Freeze).unwrap_or(base.prob_freeze),
+            prob_widen: self.probability(Effect::Widen).unwrap_or(base.prob_widen),
+            prob_granular: self
+                .probability(Effect::Granular)
+                .unwrap_or(base.prob_granular),
+            prob_beat_repeat: self
+                .probability(Effect::BeatRepeat)
+                .unwrap_or(base.prob_beat_repeat),
+            prob_gain: self.probability(Effect::Gain).unwrap_or(base.prob_gain),
+            gain_amount: self.gain_amount.unwrap_or(base.gain_amount),
+            envelopes: self.envelope.iter().fold(
+                base.envelopes,
+                |mut envelopes, &(effect, envelope)| {
+                    envelopes.set(effect, envelope);
+                    envelopes
+                },
+            ),
+            salts: self
+                .effect_seed_salt
+                .iter()
+                .fold(base.salts, |mut salts, &(effect, salt)| {
+                    salts.set(effect, salt);
+                    salts
+                }),
+            position_weight: self.position_weight.unwrap_or(base.position_weight),
+            grain_len: self.grains.map_or(base.grain_len, |(ms, _)| ms / 1000.),
+            grain_density: self
+                .grains
+                .map_or(base.grain_density, |(_, density)| density),
+            divisions: self.divisions.unwrap_or(base.divisions),
+            silence_fade: self.silence_fade.map_or(base.silence_fade, |ms| ms / 1000.),
+            silence_ramp: self.silence_ramp || base.silence_ramp,
+            crossfade: self.crossfade.map_or(base.crossfade, |ms| ms / 1000.),
+            fade_curve: self.fade_curve.unwrap_or(base.fade_curve),
+            max_swap: self.max_swap.unwrap_or(base.max_swap),
+            max_repeat: self.max_repeat.unwrap_or(base.max_repeat),
+            repeat_pitch: self.repeat_pitch.unwrap_or(base.repeat_pitch),
+            budget: self.budget.or(base.budget),
+            min_gap: self.min_gap.unwrap_or(base.min_gap),
+            each_channel_separately: self.each_channel_separately || base.each_channel_separately,
+            reverse_window: self.reverse_window || base.reverse_window,
+            reverse_above: self.reverse_above.or(base.reverse_above),
+            mid_glitch: self.mid_glitch || base.mid_glitch,
+            on_beats: self.on_beats || base.on_beats,
+            segment_dc_block: self.segment_dc_block || base.segment_dc_block,
+            dry_wet: self.dry_wet.unwrap_or(base.dry_wet),
+            underlay: self.underlay.or(base.underlay),
+            decorrelate: self.decorrelate.unwrap_or(base.decorrelate),
+            glue_ratio: self.glue.map_or(base.glue_ratio, |(ratio, _)| ratio),
+            glue_threshold: self
+                .glue
+                .map_or(base.glue_threshold, |(_, threshold)| threshold),
+            level_match: self.level_match.unwrap_or(base.level_match),
+            grid_offset: self.grid_offset.unwrap_or(base.grid_offset),
+            rotate_channels: self.rotate_channels.unwrap_or(base.rotate_channels),
+            start: self.start.unwrap_or(base.start),
+            clean_tail: self.clean_tail.unwrap_or(base.clean_tail),
+            render_tail: self.render_tail || base.render_tail,
+            int_math: self.int_math.unwrap_or(base.int_math),
+            quiet: self.quiet || base.quiet,
+        }
+    }
+
+    /// Effect options along a curve from a clean passthrough at 0 to dense glitching at 10,
+    /// the rarer effects only coming in at higher levels
+    fn complexity_config(level: u8) -> CliConfig {
+        let x = level as f64 / 10.;
         CliConfig {
-            tempo: self.tempo.unwrap_or(100.),
-            segment_length: self.segment_length.unwrap_or(0.0625),
-            prob_silence: self.prob_silence.unwrap_or(0.),
-            prob_swap: self.prob_swap.unwrap_or(0.),
-            prob_reverse: self.prob_reverse.unwrap_or(0.),
-            prob_repeat: self.prob_repeat.unwrap_or(0.),
-            max_swap: self.max_swap.unwrap_or(8),
-            max_repeat: self.max_repeat.unwrap_or(8),
-            each_channel_separately: self.each_channel_separately,
+            prob_swap: 0.3 * x,
+            prob_reverse: 0.2 * x,
+            prob_repeat: 0.25 * x,
+            prob_silence: 0.1 * x.powi(2),
+            prob_freeze: 0.1 * x.powi(2),
+            prob_beat_repeat: 0.1 * x.powi(2),
+            prob_granular: 0.05 * x.powi(3),
+            max_swap: 1 + (7. * x).round() as u16,
+            max_repeat: 2 + (6. * x).round() as u16,
+            ..Default::default()
         }
     }
 
     pub fn defaults(&self) -> String {
         let mut string = String::new();
-        if self.output.is_none() {
+        if self.output.is_none() && self.output_dir.is_none() {
             string.push_str("Using default value (`out.wav`) for output path\n");
         }
-        if self.tempo.is_none() {
+        if let Some(preset) = self.preset {
+            string.push_str(&format!(
+                "Using `{}` preset for other options not given",
+                preset.to_possible_value().unwrap().get_name()
+            ));
+            return string;
+        }
+        if let Some(level) = self.complexity {
+            string.push_str(&format!(
+                "Using complexity {level} for effect options not given"
+            ));
+            return string;
+        }
+        if self.tempo.is_none() && !self.auto_tempo {
             string.push_str("Using default value (100) for tempo\n");
         }
         if self.segment_length.is_none() {
             string.push_str("Using default value (1/16) for segment length\n");
         }
-        if self.prob_silence.is_none() {
+        if self.probability(Effect::Silence).is_none() {
             string.push_str("Using default value (0.0) for probability of silencing\n");
         }
-        if self.prob_swap.is_none() {
+        if self.probability(Effect::Swap).is_none() {
             string.push_str("Using default value (0.0) for probability of swapping\n");
         }
-        if self.prob_reverse.is_none() {
+        if self.probability(Effect::Reverse).is_none() {
             string.push_str("Using default value (0.0) for probability of reversing\n");
         }
-        if self.prob_repeat.is_none() {
+        if self.probability(Effect::Repeat).is_none() {
             string.push_str("Using default value (0.0) for probability of repeating\n");
         }
+        if self.probability(Effect::Freeze).is_none() {
+            string.push_str("Using default value (0.0) for probability of freezing\n");
+        }
+        if self.probability(Effect::Widen).is_none() {
+            string.push_str("Using default value (0.0) for probability of widening\n");
+        }
+        if self.probability(Effect::Granular).is_none() {
+            string.push_str("Using default value (0.0) for probability of granulating\n");
+        }
+        if self.probability(Effect::BeatRepeat).is_none() {
+            string.push_str("Using default value (0.0) for probability of beat repeating\n");
+        }
+        if self.probability(Effect::Gain).is_none() {
+            string.push_str("Using default value (0.0) for probability of changing gain\n");
+        }
         if self.max_swap.is_none() {
             string.push_str("Using default value (8) for maximal swap range\n");
         }
         if self.max_repeat.is_none() {
             string.push_str("Using default value (8) for maximal number of repetitions\n");
         }
+        if self.grains.is_none() {
+            string.push_str("Using default value (50:4) for grain length and density\n");
+        }
+        if self.divisions.is_none() {
+            string.push_str("Using default value (4) for number of beat repeat divisions\n");
+        }
+        if self.gain_amount.is_none() {
+            string.push_str("Using default value (0.5) for gain amount\n");
+        }
         string.pop();
         string
     }
 
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn force(&self) -> bool {
+        self.force
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn validate_only(&self) -> bool {
+        self.validate_only
+    }
+
+    pub fn examples(&self) -> bool {
+        self.examples
+    }
+
+    /// Example command lines for every effect, built from the effect's own option
+    pub fn example_lines() -> String {
+        use clap::CommandFactory;
+        let command = Cli::command();
+        let mut examples = format!("{}", "Examples:".bold().underline());
+
+        for effect in Effect::value_variants() {
+            let name = effect.to_possible_value().unwrap().get_name().to_string();
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_id() == format!("prob_{}", name.replace('-', "_")).as_str())
+                .unwrap();
+            let flag = match arg.get_short() {
+                Some(short) => format!("-{short}"),
+                None => format!("--{}", arg.get_long().unwrap()),
+            };
+            examples += &format!(
+                "\n  {}\n  $ wavglitch in.wav -o {name}.wav {flag} 0.25\n",
+                arg.get_help().unwrap()
+            );
+        }
+
+        examples
+    }
+
+    pub fn benchmark(&self) -> bool {
+        self.benchmark
+    }
+
+    pub fn merge(&self) -> Vec<Vec<u16>> {
+        self.merge.clone()
+    }
+
+    pub fn matrix(&self) -> Option<PathBuf> {
+        self.matrix.clone()
+    }
+
+    pub fn channel_order(&self) -> Vec<u16> {
+        self.channel_order.clone()
+    }
+
+    pub fn read_mode(&self) -> ReadMode {
+        #[cfg(feature = "mmap")]
+        if self.mmap {
+            return ReadMode::Mapped;
+        }
+        self.read_buffer_size.map_or(ReadMode::default(), |size| {
+            ReadMode::Buffered(size as usize)
+        })
+    }
+
+    pub fn max_channels(&self) -> u16 {
+        self.max_channels.unwrap_or(32)
+    }
+
+    pub fn transient_segments(&self) -> bool {
+        self.transient_segments
+    }
+
+    pub fn prob_curve_file(&self) -> Option<PathBuf> {
+        self.prob_curve_file.clone()
+    }
+
+    pub fn prob_matrix(&self) -> Option<PathBuf> {
+        self.prob_matrix.clone()
+    }
+
+    pub fn sidechain(&self) -> Option<PathBuf> {
+        self.sidechain.clone()
+    }
+
+    pub fn layout_cache(&self) -> Option<PathBuf> {
+        self.layout_cache.clone()
+    }
+
+    pub fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    pub fn base64(&self) -> bool {
+        self.base64
+    }
+
+    pub fn check_input_clipping(&self) -> bool {
+        self.check_input_clipping
+    }
+
+    pub fn dc_report(&self) -> bool {
+        self.dc_report
+    }
+
+    pub fn resource_report(&self) -> bool {
+        self.resource_report
+    }
+
+    pub fn coverage(&self) -> bool {
+        self.coverage
+    }
+
+    pub fn seed_variance(&self) -> Option<u16> {
+        self.seed_variance
+    }
+
+    pub fn seed_sweep(&self) -> Option<(u64, u64, f64)> {
+        self.seed_sweep
+    }
+
+    pub fn lufs(&self) -> Option<f64> {
+        self.lufs
+    }
+
+    /// Whether the tempo is to be estimated from the input
+    pub fn auto_tempo(&self) -> bool {
+        self.auto_tempo && self.tempo.is_none()
+    }
+
+    /// Uses `tempo` unless one is given explicitly
+    pub fn set_detected_tempo(&self, tempo: f64) {
+        let _ = self.detected_tempo.set(tempo);
+    }
+
+    pub fn peak_mode(&self) -> PeakMode {
+        self.peak_mode.unwrap_or_default()
+    }
+
+    pub fn auto_bit_depth(&self) -> bool {
+        self.auto_bit_depth
+    }
+
+    pub fn float(&self) -> bool {
+        self.float
+    }
+
+    pub fn trim_output_silence(&self) -> bool {
+        self.trim_output_silence
+    }
+
+    /// Whether the whole output is held in memory for a post pass before writing
+    pub fn buffered_output(&self) -> bool {
+        self.auto_bit_depth
+            || self.float
+            || self.lufs.is_some()
+            || self.trim_output_silence
+            || self.parallel_chain.is_some()
+    }
+
+    /// Configuration of the second chain given by `--parallel-chain`, its options over the
+    /// options of the main chain
+    pub fn parallel_config(&self) -> Result<Option<CliConfig>, clap::Error> {
+        use clap::{CommandFactory, FromArgMatches};
+        let Some(options) = &self.parallel_chain else {
+            return Ok(None);
+        };
+        let args = ["wavglitch", "-"]
+            .into_iter()
+            .chain(options.split_whitespace());
+        let mut command = Cli::command();
+        let matches = command.try_get_matches_from_mut(args)?;
+        let shared = command.get_arguments().find(|arg| {
+            let id = arg.get_id().as_str();
+            id != "input"
+                && !Self::CHAIN_OPTIONS.contains(&id)
+                && matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine)
+        });
+        if let Some(long) = shared.and_then(|arg| arg.get_long()) {
+            let message = format!("`--{long}` cannot be set for a parallel chain");
+            return Err(command.error(clap::error::ErrorKind::ArgumentConflict, message));
+        }
+        Ok(Some(
+            Cli::from_arg_matches(&matches)?.config_over(self.config()),
+        ))
+    }
+
+    pub fn max_output_size(&self) -> Option<u64> {
+        self.max_output_size.map(|size| (size * 1e6) as u64)
+    }
+
+    pub fn channel_delays(&self) -> Vec<u32> {
+        self.channel_delays.clone()
+    }
+
+    pub fn peaks(&self) -> Option<PathBuf> {
+        self.peaks.clone()
+    }
+
+    pub fn analysis(&self) -> Option<PathBuf> {
+        self.analysis.clone()
+    }
+
+    pub fn log(&self) -> Option<PathBuf> {
+        self.log.clone()
+    }
+
+    pub fn intermediate(&self) -> Option<PathBuf> {
+        self.intermediate.clone()
+    }
+
+    pub fn layout_svg(&self) -> Option<PathBuf> {
+        self.layout_svg.clone()
+    }
+
+    pub fn meta(&self) -> Vec<([u8; 4], String)> {
+        let mut meta = self.meta.clone();
+        if self.timestamp && meta.iter().all(|(id, _)| id != b"ICRD") {
+            let [year, month, day, ..] = Self::utc(self.started());
+            meta.push((*b"ICRD", format!("{year:04}-{month:02}-{day:02}")));
+        }
+        meta
+    }
+
+    pub fn loop_points(&self) -> Option<(u32, u32)> {
+        self.loop_points
+    }
+
+    pub fn append(&self) -> bool {
+        self.append
+    }
+
+    #[cfg(feature = "fft")]
+    pub fn reverb(&self) -> Option<PathBuf> {
+        self.reverb.clone()
+    }
+
+    pub fn warnings(&self, wav_config: WavConfig) -> Vec<String> {
+        let config = self.config();
+        // An unreadable matrix is reported when the layout is built, so it counts as enabling
+        // every effect here
+        let configs = match &self.prob_matrix {
+            Some(path) => SegmentLayout::read_prob_matrix(path, config, wav_config.channels)
+                .unwrap_or_default(),
+            None => vec![config],
+        };
+        let active = |effect| {
+            configs.is_empty()
+                || configs.iter().any(|config| {
+                    [0., 0.5, 1.]
+                        .iter()
+                        .any(|&position| config.probability(effect, position) > 0.)
+                })
+        };
+        let mut warnings = vec![];
+        if config.each_channel_separately && wav_config.channels == 1 {
+            warnings.push(
+                "processing each channel separately has no effect on a mono input".to_string(),
+            );
+        }
+        if active(Effect::Widen) && wav_config.channels != 2 {
+            warnings.push("widening has no effect on a non-stereo input".to_string());
+        }
+        if self.transient_segments && self.grid_offset.is_some() {
+            warnings.push("grid offset has no effect with transient segments".to_string());
+        }
+        let movable_count = SegmentLayout::movable_count(config, wav_config);
+        if active(Effect::Swap) && movable_count < 2 {
+            warnings.push("swapping has no effect with fewer than two whole segments".to_string());
+        }
+        if active(Effect::Repeat) && movable_count < 2 {
+            warnings.push("repeating has no effect with fewer than two whole segments".to_string());
+        }
+        if self.max_swap.is_some() && !active(Effect::Swap) {
+            warnings.push("maximal swap range has no effect without swapping".to_string());
+        }
+        if self.max_repeat.is_some() && !active(Effect::Repeat) {
+            warnings
+                .push("maximal number of repetitions has no effect without repeating".to_string());
+        }
+        if self.repeat_pitch.is_some() && !active(Effect::Repeat) {
+            warnings.push("repeat pitch has no effect without repeating".to_string());
+        }
+        if self.silence_fade.is_some() && !active(Effect::Silence) {
+            warnings.push("silence fade has no effect without silencing".to_string());
+        }
+        if self.silence_ramp && !active(Effect::Silence) {
+            warnings.push("silence ramp has no effect without silencing".to_string());
+        }
+        if self.reverse_window && !active(Effect::Reverse) {
+            warnings.push("reverse window has no effect without reversing".to_string());
+        }
+        if self.reverse_above.is_some() && !active(Effect::Reverse) {
+            warnings.push("reverse threshold has no effect without reversing".to_string());
+        }
+        if self.fade_curve.is_some() && self.silence_fade.is_none() && !self.reverse_window {
+            warnings.push("fade curve has no effect without fades".to_string());
+        }
+        if self.grains.is_some() && !active(Effect::Granular) {
+            warnings
+                .push("grain length and density have no effect without granulating".to_string());
+        }
+        if self.divisions.is_some() && !active(Effect::BeatRepeat) {
+            warnings.push("divisions have no effect without beat repeating".to_string());
+        }
+        if self.gain_amount.is_some() && !active(Effect::Gain) {
+            warnings.push("gain amount has no effect without gain".to_string());
+        }
+        warnings
+    }
+
     fn tempo_parser(s: &str) -> Result<f64, String> {
         let tempo: f64 = s.parse().map_err(|e| format!("{e}"))?;
 
@@ -130,6 +1312,13 @@ impl Cli {
     }
 
     fn segment_parser(s: &str) -> Result<f64, String> {
+        let (s, factor) = if let Some(s) = s.strip_suffix('.') {
+            (s, 1.5)
+        } else if let Some(s) = s.strip_suffix('t') {
+            (s, 2. / 3.)
+        } else {
+            (s, 1.)
+        };
         let v: Vec<_> = s.split('/').collect();
         if v.len() != 2 {
             return Err("segment length must be in x/y format".to_string());
@@ -139,58 +1328,584 @@ impl Cli {
         if n == 0 || d == 0 {
             return Err("both numbers must be in 1..=65535".to_string());
         }
-        Ok(n as f64 / d as f64)
+        Ok(n as f64 / d as f64 * factor)
+    }
+
+    fn seconds_parser(s: &str) -> Result<f64, String> {
+        let seconds: f64 = s.parse().map_err(|e| format!("{e}"))?;
+
+        if seconds.is_finite() && seconds >= 0. {
+            Ok(seconds)
+        } else {
+            Err(format!("{seconds} is not a non-negative number of seconds"))
+        }
+    }
+
+    fn size_parser(s: &str) -> Result<f64, String> {
+        let size: f64 = s.parse().map_err(|e| format!("{e}"))?;
+
+        if size.is_finite() && size > 0. {
+            Ok(size)
+        } else {
+            Err(format!("{size} is not a positive number of megabytes"))
+        }
+    }
+
+    fn lufs_parser(s: &str) -> Result<f64, String> {
+        let lufs: f64 = s.parse().map_err(|e| format!("{e}"))?;
+
+        if (-70f64..=0f64).contains(&lufs) {
+            Ok(lufs)
+        } else {
+            Err(format!("{lufs} is not in -70.0..=0.0"))
+        }
+    }
+
+    fn semitones_parser(s: &str) -> Result<f64, String> {
+        let semitones: f64 = s.parse().map_err(|e| format!("{e}"))?;
+
+        if (-24f64..=24f64).contains(&semitones) {
+            Ok(semitones)
+        } else {
+            Err(format!("{semitones} is not in -24.0..=24.0"))
+        }
+    }
+
+    fn gain_parser(s: &str) -> Result<f64, String> {
+        let gain: f64 = s.parse().map_err(|e| format!("{e}"))?;
+
+        if (0f64..=4f64).contains(&gain) {
+            Ok(gain)
+        } else {
+            Err(format!("{gain} is not in 0.0..=4.0"))
+        }
+    }
+
+    fn milliseconds_parser(s: &str) -> Result<f64, String> {
+        let ms: f64 = s.parse().map_err(|e| format!("{e}"))?;
+
+        if ms.is_finite() && ms >= 0. {
+            Ok(ms)
+        } else {
+            Err(format!("{ms} is not a non-negative number of milliseconds"))
+        }
+    }
+
+    fn effect_parser(s: &str) -> Result<(Effect, f64), String> {
+        let (effect, probability) = s
+            .split_once('=')
+            .ok_or("effect must be in effect=prob format".to_string())?;
+        Ok((
+            Effect::from_str(effect, false)?,
+            Self::probability_parser(probability)?,
+        ))
+    }
+
+    fn envelope_parser(s: &str) -> Result<(Effect, (f64, f64)), String> {
+        let (effect, ramp) = s
+            .split_once('=')
+            .ok_or("envelope must be in effect=start:end format".to_string())?;
+        let effect = Effect::from_str(effect, false)?;
+        let (start, end) = ramp
+            .split_once(':')
+            .ok_or("envelope must be in effect=start:end format".to_string())?;
+        Ok((
+            effect,
+            (
+                Self::probability_parser(start)?,
+                Self::probability_parser(end)?,
+            ),
+        ))
+    }
+
+    fn salt_parser(s: &str) -> Result<(Effect, u64), String> {
+        let (effect, salt) = s
+            .split_once('=')
+            .ok_or("salt must be in effect=salt format".to_string())?;
+        Ok((
+            Effect::from_str(effect, false)?,
+            salt.parse().map_err(|e| format!("{e}"))?,
+        ))
+    }
+
+    fn group_parser(s: &str) -> Result<Vec<u16>, String> {
+        s.split(',')
+            .map(|channel| channel.parse().map_err(|e| format!("{e}")))
+            .collect()
+    }
+
+    fn glue_parser(s: &str) -> Result<(f64, f64), String> {
+        let (ratio, threshold) = s
+            .split_once(':')
+            .ok_or("glue must be in ratio:threshold format".to_string())?;
+        let ratio: f64 = ratio.parse().map_err(|e| format!("{e}"))?;
+        let threshold: f64 = threshold.parse().map_err(|e| format!("{e}"))?;
+        if !(1f64..=100f64).contains(&ratio) {
+            return Err(format!("{ratio} is not in 1.0..=100.0"));
+        }
+        if !(-96f64..=0f64).contains(&threshold) {
+            return Err(format!("{threshold} is not in -96.0..=0.0"));
+        }
+        Ok((ratio, threshold))
+    }
+
+    fn dbfs_parser(s: &str) -> Result<f64, String> {
+        let dbfs: f64 = s.parse().map_err(|e| format!("{e}"))?;
+
+        if (-96f64..=0f64).contains(&dbfs) {
+            Ok(dbfs)
+        } else {
+            Err(format!("{dbfs} is not in -96.0..=0.0"))
+        }
+    }
+
+    fn grains_parser(s: &str) -> Result<(f64, f64), String> {
+        let (ms, density) = s
+            .split_once(':')
+            .ok_or("grains must be in ms:density format".to_string())?;
+        let ms: f64 = ms.parse().map_err(|e| format!("{e}"))?;
+        let density: f64 = density.parse().map_err(|e| format!("{e}"))?;
+        if !(ms.is_finite() && ms > 0.) {
+            return Err(format!("{ms} is not a positive grain length"));
+        }
+        if !(1f64..=64f64).contains(&density) {
+            return Err(format!("{density} is not in 1.0..=64.0"));
+        }
+        Ok((ms, density))
+    }
+
+    fn seed_parser(s: &str) -> Result<u64, String> {
+        match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map_err(|e| format!("{e}"))
+    }
+
+    fn seed_sweep_parser(s: &str) -> Result<(u64, u64, f64), String> {
+        let [start, end, secs] = s.split(':').collect::<Vec<_>>()[..] else {
+            return Err("seed sweep must be in start:end:secs format".to_string());
+        };
+        let start = Self::seed_parser(start)?;
+        let end = Self::seed_parser(end)?;
+        let secs: f64 = secs.parse().map_err(|e| format!("{e}"))?;
+        if start > end {
+            return Err(format!("seed {start} is after seed {end}"));
+        }
+        if !(secs.is_finite() && secs > 0.) {
+            return Err(format!("{secs} is not a positive preview length"));
+        }
+        Ok((start, end, secs))
+    }
+
+    fn loop_parser(s: &str) -> Result<(u32, u32), String> {
+        let (start, end) = s
+            .split_once(':')
+            .ok_or("loop points must be in start:end format".to_string())?;
+        let start: u32 = start.parse().map_err(|e| format!("{e}"))?;
+        let end: u32 = end.parse().map_err(|e| format!("{e}"))?;
+        if start >= end {
+            return Err(format!("loop start {start} is not before end {end}"));
+        }
+        Ok((start, end))
+    }
+
+    fn meta_parser(s: &str) -> Result<([u8; 4], String), String> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or("metadata must be in key=value format".to_string())?;
+        let id = match key {
+            "title" => *b"INAM",
+            "artist" => *b"IART",
+            "comment" => *b"ICMT",
+            "date" => *b"ICRD",
+            "genre" => *b"IGNR",
+            "software" => *b"ISFT",
+            _ => key
+                .as_bytes()
+                .try_into()
+                .ok()
+                .filter(|id: &[u8; 4]| id.iter().all(u8::is_ascii_alphanumeric))
+                .ok_or(format!("{key} is not a known key or a four-letter ID"))?,
+        };
+        if value.contains('\0') {
+            return Err("metadata value must not contain NUL".to_string());
+        }
+        Ok((id, value.to_string()))
     }
 
     fn probability_parser(s: &str) -> Result<f64, String> {
         let probability: f64 = s.parse().map_err(|e| format!("{e}"))?;
 
-        if (0f64..=1f64).contains(&probability) {
-            Ok(probability)
-        } else {
-            Err(format!("{probability} is not in 0.0..=1.0"))
-        }
+        if (0f64..=1f64).contains(&probability) {
+            Ok(probability)
+        } else {
+            Err(format!("{probability} is not in 0.0..=1.0"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn defaults_all() {
+        let cli = Cli::try_parse_from(["test", "in.wav"]).unwrap();
+
+        assert_eq!(
+            cli.defaults(),
+            "Using default value (`out.wav`) for output path\n\
+             Using default value (100) for tempo\n\
+             Using default value (1/16) for segment length\n\
+             Using default value (0.0) for probability of silencing\n\
+             Using default value (0.0) for probability of swapping\n\
+             Using default value (0.0) for probability of reversing\n\
+             Using default value (0.0) for probability of repeating\n\
+             Using default value (0.0) for probability of freezing\n\
+             Using default value (0.0) for probability of widening\n\
+             Using default value (0.0) for probability of granulating\n\
+             Using default value (0.0) for probability of beat repeating\n\
+             Using default value (0.0) for probability of changing gain\n\
+             Using default value (8) for maximal swap range\n\
+             Using default value (8) for maximal number of repetitions\n\
+             Using default value (50:4) for grain length and density\n\
+             Using default value (4) for number of beat repeat divisions\n\
+             Using default value (0.5) for gain amount"
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn defaults_none() {
+        let cli = Cli::try_parse_from([
+            "test",
+            "in.wav",
+            "-o",
+            "out.wav",
+            "-t",
+            "1",
+            "-l",
+            "1/1",
+            "-s",
+            "1",
+            "-w",
+            "1",
+            "-r",
+            "1",
+            "-p",
+            "1",
+            "--freeze",
+            "1",
+            "--widen",
+            "1",
+            "--granular",
+            "1",
+            "--grains",
+            "10:2",
+            "--beat-repeat",
+            "1",
+            "--divisions",
+            "8",
+            "-g",
+            "1",
+            "--gain-amount",
+            "2",
+            "-a",
+            "1",
+            "-n",
+            "1",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.defaults(), "".to_string());
+    }
+
+    #[test]
+    fn defaults_preset() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "--preset", "stutter"]).unwrap();
+
+        assert_eq!(
+            cli.defaults(),
+            "Using default value (`out.wav`) for output path\n\
+             Using `stutter` preset for other options not given"
+        );
+    }
+
+    #[test]
+    fn config_preset() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "--preset", "breakcore"]).unwrap();
+
+        assert_eq!(
+            cli.config(),
+            CliConfig {
+                prob_silence: 0.1,
+                prob_swap: 0.4,
+                prob_reverse: 0.15,
+                prob_repeat: 0.25,
+                max_swap: 4,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn config_preset_overridden() {
+        let cli = Cli::try_parse_from([
+            "test", "in.wav", "--preset", "stutter", "-p", "0.5", "-l", "1/8",
+        ])
+        .unwrap();
+        let config = cli.config();
+
+        assert_eq!(config.prob_repeat, 0.5);
+        assert_eq!(config.segment_length, 0.125);
+        assert_eq!(config.max_repeat, 4);
+    }
+
+    #[test]
+    fn config_complexity() {
+        let wav_config = WavConfig {
+            duration: 48000 * 1200,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let coverage = |level: &str| {
+            let cli = Cli::try_parse_from(["test", "in.wav", "--complexity", level]).unwrap();
+            SegmentLayout::build(
+                cli.config(),
+                wav_config,
+                &[],
+                &[],
+                &mut StdRng::seed_from_u64(0),
+            )
+            .coverage()
+            .into_iter()
+            .map(|(_, fraction)| fraction)
+            .sum::<f64>()
+        };
+
+        assert_eq!(coverage("0"), 0.);
+        let coverages: Vec<f64> = (1..=10).map(|level| coverage(&level.to_string())).collect();
+        assert!(coverages[0] > 0.);
+        assert!(
+            coverages.windows(2).all(|pair| pair[0] < pair[1]),
+            "{coverages:?}"
+        );
+
+        let cli = Cli::try_parse_from(["test", "in.wav", "--complexity", "10", "-r", "0"]).unwrap();
+        assert_eq!(cli.config().prob_reverse, 0.);
+        assert_eq!(cli.config().prob_swap, 0.3);
+    }
+
+    #[test]
+    fn warnings_channels_mono() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "-c"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+
+        assert_eq!(
+            cli.warnings(wav_config),
+            ["processing each channel separately has no effect on a mono input"]
+        );
+    }
+
+    #[test]
+    fn warnings_range_number() {
+        let cli =
+            Cli::try_parse_from(["test", "in.wav", "-a", "4", "-n", "4", "-p", "0.5"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert_eq!(
+            cli.warnings(wav_config),
+            ["maximal swap range has no effect without swapping"]
+        );
+    }
+
+    #[test]
+    fn warnings_reverse_window() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "--reverse-window"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert_eq!(
+            cli.warnings(wav_config),
+            ["reverse window has no effect without reversing"]
+        );
+    }
+
+    #[test]
+    fn warnings_short_input() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "-w", "0.5", "-p", "0.5"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 10000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert_eq!(
+            cli.warnings(wav_config),
+            [
+                "swapping has no effect with fewer than two whole segments",
+                "repeating has no effect with fewer than two whole segments"
+            ]
+        );
+    }
+
+    #[test]
+    fn warnings_swap_max_1() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "-w", "0.5", "-a", "1"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert!(cli.warnings(wav_config).is_empty());
+    }
+
+    #[test]
+    fn warnings_repeat_max_1() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "-p", "0.5", "-n", "1"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert!(cli.warnings(wav_config).is_empty());
+    }
+
+    #[test]
+    fn warnings_grid_offset_transient() {
+        let cli = Cli::try_parse_from([
+            "test",
+            "in.wav",
+            "--transient-segments",
+            "--grid-offset",
+            "100",
+        ])
+        .unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert_eq!(
+            cli.warnings(wav_config),
+            ["grid offset has no effect with transient segments"]
+        );
+    }
+
+    #[test]
+    fn warnings_preset() {
+        let cli =
+            Cli::try_parse_from(["test", "in.wav", "--preset", "breakcore", "-a", "2"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert!(cli.warnings(wav_config).is_empty());
+    }
+
+    #[test]
+    fn warnings_envelope() {
+        let cli =
+            Cli::try_parse_from(["test", "in.wav", "--envelope", "swap=0:1", "-a", "2"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert!(cli.warnings(wav_config).is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn warnings_envelope_position_weight() {
+        let cli = Cli::try_parse_from([
+            "test",
+            "in.wav",
+            "--envelope",
+            "swap=1:0",
+            "--position-weight",
+            "ramp-up",
+            "-a",
+            "2",
+        ])
+        .unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        assert!(cli.warnings(wav_config).is_empty());
+    }
 
     #[test]
-    fn defaults_all() {
-        let cli = Cli::try_parse_from(["test", "in.wav"]).unwrap();
+    fn warnings_prob_matrix() {
+        let dir = TempDir::new().unwrap();
+        let matrix = dir.child("matrix.txt");
+        matrix
+            .write_str(
+                "0 0.5 0 0 0 0 0 0 0
+0 0 0 0 0 0 0 0 0
+",
+            )
+            .unwrap();
+        let warnings = |args: &[&str]| {
+            let matrix = matrix.to_str().unwrap();
+            Cli::try_parse_from([&["test", "in.wav", "--prob-matrix", matrix], args].concat())
+                .unwrap()
+                .warnings(WavConfig {
+                    duration: 48000,
+                    sample_rate: 48000,
+                    channels: 2,
+                })
+        };
 
+        assert!(warnings(&["-a", "4"]).is_empty());
         assert_eq!(
-            cli.defaults(),
-            "Using default value (`out.wav`) for output path\n\
-             Using default value (100) for tempo\n\
-             Using default value (1/16) for segment length\n\
-             Using default value (0.0) for probability of silencing\n\
-             Using default value (0.0) for probability of swapping\n\
-             Using default value (0.0) for probability of reversing\n\
-             Using default value (0.0) for probability of repeating\n\
-             Using default value (8) for maximal swap range\n\
-             Using default value (8) for maximal number of repetitions"
-                .to_string()
+            warnings(&["-n", "4"]),
+            ["maximal number of repetitions has no effect without repeating"]
         );
+
+        dir.close().unwrap();
     }
 
     #[test]
-    fn defaults_none() {
-        let cli = Cli::try_parse_from([
-            "test", "in.wav", "-o", "out.wav", "-t", "1", "-l", "1/1", "-s", "1", "-w", "1", "-r",
-            "1", "-p", "1", "-a", "1", "-n", "1",
-        ])
-        .unwrap();
+    fn warnings_none() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "-c", "-w", "0.5", "-a", "4"]).unwrap();
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
 
-        assert_eq!(cli.defaults(), "".to_string());
+        assert!(cli.warnings(wav_config).is_empty());
     }
 
     #[test]
     fn tempo_parser_not_float() {
         assert_eq!(
-            Cli::tempo_parser(&"float"),
+            Cli::tempo_parser("float"),
             Err("invalid float literal".to_string())
         );
     }
@@ -198,7 +1913,7 @@ mod tests {
     #[test]
     fn tempo_parser_lesser() {
         assert_eq!(
-            Cli::tempo_parser(&"0.25"),
+            Cli::tempo_parser("0.25"),
             Err("0.25 is not in 1.0..=4095.0".to_string())
         );
     }
@@ -206,7 +1921,7 @@ mod tests {
     #[test]
     fn tempo_parser_greater() {
         assert_eq!(
-            Cli::tempo_parser(&"5000"),
+            Cli::tempo_parser("5000"),
             Err("5000 is not in 1.0..=4095.0".to_string())
         );
     }
@@ -214,7 +1929,7 @@ mod tests {
     #[test]
     fn tempo_parser_nan() {
         assert_eq!(
-            Cli::tempo_parser(&"NaN"),
+            Cli::tempo_parser("NaN"),
             Err("NaN is not in 1.0..=4095.0".to_string())
         );
     }
@@ -222,20 +1937,20 @@ mod tests {
     #[test]
     fn tempo_parser_inf() {
         assert_eq!(
-            Cli::tempo_parser(&"inf"),
+            Cli::tempo_parser("inf"),
             Err("inf is not in 1.0..=4095.0".to_string())
         );
     }
 
     #[test]
     fn tempo_parser_ok() {
-        assert_eq!(Cli::tempo_parser(&"100"), Ok(100f64));
+        assert_eq!(Cli::tempo_parser("100"), Ok(100f64));
     }
 
     #[test]
     fn segment_parser_two_div() {
         assert_eq!(
-            Cli::segment_parser(&"1/2/4"),
+            Cli::segment_parser("1/2/4"),
             Err("segment length must be in x/y format".to_string())
         );
     }
@@ -243,7 +1958,7 @@ mod tests {
     #[test]
     fn segment_parser_no_div() {
         assert_eq!(
-            Cli::segment_parser(&"."),
+            Cli::segment_parser("."),
             Err("segment length must be in x/y format".to_string())
         );
     }
@@ -251,7 +1966,7 @@ mod tests {
     #[test]
     fn segment_parser_not_int() {
         assert_eq!(
-            Cli::segment_parser(&"a/4"),
+            Cli::segment_parser("a/4"),
             Err("invalid digit found in string".to_string())
         );
     }
@@ -259,7 +1974,7 @@ mod tests {
     #[test]
     fn segment_parser_zero_n() {
         assert_eq!(
-            Cli::segment_parser(&"0/4"),
+            Cli::segment_parser("0/4"),
             Err("both numbers must be in 1..=65535".to_string())
         );
     }
@@ -267,20 +1982,409 @@ mod tests {
     #[test]
     fn segment_parser_zero_d() {
         assert_eq!(
-            Cli::segment_parser(&"1/0"),
+            Cli::segment_parser("1/0"),
             Err("both numbers must be in 1..=65535".to_string())
         );
     }
 
     #[test]
     fn segment_parser_ok() {
-        assert_eq!(Cli::segment_parser(&"1/4"), Ok(0.25f64));
+        assert_eq!(Cli::segment_parser("1/4"), Ok(0.25f64));
+    }
+
+    #[test]
+    fn segment_parser_dotted() {
+        assert_eq!(Cli::segment_parser("1/8."), Ok(0.1875f64));
+    }
+
+    #[test]
+    fn segment_parser_triplet() {
+        assert_eq!(Cli::segment_parser("1/8t"), Ok(1. / 12.));
+    }
+
+    #[test]
+    fn segment_parser_dotted_triplet() {
+        assert_eq!(
+            Cli::segment_parser("1/8.t"),
+            Err("invalid digit found in string".to_string())
+        );
+    }
+
+    #[test]
+    fn segment_parser_unknown_suffix() {
+        assert_eq!(
+            Cli::segment_parser("1/8x"),
+            Err("invalid digit found in string".to_string())
+        );
+    }
+
+    #[test]
+    fn seconds_parser_negative() {
+        assert_eq!(
+            Cli::seconds_parser("-1"),
+            Err("-1 is not a non-negative number of seconds".to_string())
+        );
+    }
+
+    #[test]
+    fn seconds_parser_inf() {
+        assert_eq!(
+            Cli::seconds_parser("inf"),
+            Err("inf is not a non-negative number of seconds".to_string())
+        );
+    }
+
+    #[test]
+    fn seconds_parser_ok() {
+        assert_eq!(Cli::seconds_parser("1.5"), Ok(1.5f64));
+    }
+
+    #[test]
+    fn size_parser_zero() {
+        assert_eq!(
+            Cli::size_parser("0"),
+            Err("0 is not a positive number of megabytes".to_string())
+        );
+    }
+
+    #[test]
+    fn size_parser_ok() {
+        assert_eq!(Cli::size_parser("0.5"), Ok(0.5f64));
+    }
+
+    #[test]
+    fn lufs_parser_greater() {
+        assert_eq!(
+            Cli::lufs_parser("3"),
+            Err("3 is not in -70.0..=0.0".to_string())
+        );
+    }
+
+    #[test]
+    fn lufs_parser_ok() {
+        assert_eq!(Cli::lufs_parser("-14"), Ok(-14f64));
+    }
+
+    #[test]
+    fn gain_parser_greater() {
+        assert_eq!(
+            Cli::gain_parser("5"),
+            Err("5 is not in 0.0..=4.0".to_string())
+        );
+    }
+
+    #[test]
+    fn semitones_parser_lesser() {
+        assert_eq!(
+            Cli::semitones_parser("-25"),
+            Err("-25 is not in -24.0..=24.0".to_string())
+        );
+    }
+
+    #[test]
+    fn milliseconds_parser_negative() {
+        assert_eq!(
+            Cli::milliseconds_parser("-5"),
+            Err("-5 is not a non-negative number of milliseconds".to_string())
+        );
+    }
+
+    #[test]
+    fn milliseconds_parser_ok() {
+        assert_eq!(Cli::milliseconds_parser("2.5"), Ok(2.5f64));
+    }
+
+    #[test]
+    fn effect_parser_no_equals() {
+        assert_eq!(
+            Cli::effect_parser("reverse"),
+            Err("effect must be in effect=prob format".to_string())
+        );
+    }
+
+    #[test]
+    fn effect_parser_unknown_effect() {
+        assert_eq!(
+            Cli::effect_parser("echo=0.5"),
+            Err("invalid variant: echo".to_string())
+        );
+    }
+
+    #[test]
+    fn effect_parser_greater() {
+        assert_eq!(
+            Cli::effect_parser("silence=1.5"),
+            Err("1.5 is not in 0.0..=1.0".to_string())
+        );
+    }
+
+    #[test]
+    fn effect_parser_ok() {
+        assert_eq!(Cli::effect_parser("freeze=0.5"), Ok((Effect::Freeze, 0.5)));
+    }
+
+    #[test]
+    fn config_effects() {
+        let cli = Cli::try_parse_from([
+            "test",
+            "in.wav",
+            "--effects",
+            "reverse=0.5,silence=0.2,swap=0.1",
+            "-w",
+            "0.3",
+        ])
+        .unwrap();
+        let config = cli.config();
+
+        assert_eq!(config.prob_reverse, 0.5);
+        assert_eq!(config.prob_silence, 0.2);
+        assert_eq!(config.prob_swap, 0.3);
+        assert_eq!(config.prob_repeat, 0.);
+        assert!(!cli.defaults().contains("probability of reversing"));
+    }
+
+    #[test]
+    fn envelope_parser_unknown_effect() {
+        assert_eq!(
+            Cli::envelope_parser("echo=0:1"),
+            Err("invalid variant: echo".to_string())
+        );
+    }
+
+    #[test]
+    fn envelope_parser_ok() {
+        assert_eq!(
+            Cli::envelope_parser("repeat=0.5:0"),
+            Ok((Effect::Repeat, (0.5, 0.)))
+        );
+    }
+
+    #[test]
+    fn config_envelopes() {
+        let cli = Cli::try_parse_from([
+            "test",
+            "in.wav",
+            "-s",
+            "0.5",
+            "--envelope",
+            "swap=0:1",
+            "--envelope",
+            "reverse=1:0.5",
+        ])
+        .unwrap();
+        let config = cli.config();
+
+        assert_eq!(config.probability(Effect::Swap, 0.25), 0.25);
+        assert_eq!(config.probability(Effect::Reverse, 0.5), 0.75);
+        assert_eq!(config.probability(Effect::Silence, 1.), 0.5);
+    }
+
+    #[test]
+    fn merge_groups() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "--merge", "0,1:2,3"]).unwrap();
+
+        assert_eq!(cli.merge(), [vec![0, 1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn group_parser_not_int() {
+        assert_eq!(
+            Cli::group_parser("0,a"),
+            Err("invalid digit found in string".to_string())
+        );
+    }
+
+    #[test]
+    fn seed_parser() {
+        assert_eq!(Cli::seed_parser("42"), Ok(42));
+        assert_eq!(Cli::seed_parser("0x2a"), Ok(42));
+        assert_eq!(
+            Cli::seed_parser(&SeedFormat::Hex.format(u64::MAX)),
+            Ok(u64::MAX)
+        );
+        assert_eq!(
+            Cli::seed_parser("0xg"),
+            Err("invalid digit found in string".to_string())
+        );
+    }
+
+    #[test]
+    fn seed_sweep_parser() {
+        assert_eq!(Cli::seed_sweep_parser("3:5:0.25"), Ok((3, 5, 0.25)));
+        assert_eq!(
+            Cli::seed_sweep_parser("5:3:1"),
+            Err("seed 5 is after seed 3".to_string())
+        );
+        assert_eq!(
+            Cli::seed_sweep_parser("3:5"),
+            Err("seed sweep must be in start:end:secs format".to_string())
+        );
+    }
+
+    #[test]
+    fn glue_parser_ratio() {
+        assert_eq!(
+            Cli::glue_parser("0.5:-12"),
+            Err("0.5 is not in 1.0..=100.0".to_string())
+        );
+    }
+
+    #[test]
+    fn glue_parser_threshold() {
+        assert_eq!(
+            Cli::glue_parser("4:6"),
+            Err("6 is not in -96.0..=0.0".to_string())
+        );
+    }
+
+    #[test]
+    fn glue_parser_ok() {
+        assert_eq!(Cli::glue_parser("4:-12.5"), Ok((4f64, -12.5f64)));
+    }
+
+    #[test]
+    fn grains_parser_zero_len() {
+        assert_eq!(
+            Cli::grains_parser("0:4"),
+            Err("0 is not a positive grain length".to_string())
+        );
+    }
+
+    #[test]
+    fn grains_parser_density() {
+        assert_eq!(
+            Cli::grains_parser("50:0.5"),
+            Err("0.5 is not in 1.0..=64.0".to_string())
+        );
+    }
+
+    #[test]
+    fn grains_parser_ok() {
+        assert_eq!(Cli::grains_parser("25:2.5"), Ok((25f64, 2.5f64)));
+    }
+
+    #[test]
+    fn loop_parser_no_colon() {
+        assert_eq!(
+            Cli::loop_parser("100"),
+            Err("loop points must be in start:end format".to_string())
+        );
+    }
+
+    #[test]
+    fn loop_parser_reversed() {
+        assert_eq!(
+            Cli::loop_parser("200:100"),
+            Err("loop start 200 is not before end 100".to_string())
+        );
+    }
+
+    #[test]
+    fn loop_parser_ok() {
+        assert_eq!(Cli::loop_parser("100:4899"), Ok((100, 4899)));
+    }
+
+    #[test]
+    fn parallel_config() {
+        let cli = Cli::try_parse_from([
+            "test",
+            "in.wav",
+            "-r",
+            "0.5",
+            "--start",
+            "2",
+            "--parallel-chain",
+            "-s 0.25 -t 140",
+        ])
+        .unwrap();
+        let parallel_config = cli.parallel_config().unwrap().unwrap();
+
+        assert_eq!(parallel_config.prob_silence, 0.25);
+        assert_eq!(parallel_config.prob_reverse, 0.5);
+        assert_eq!(parallel_config.tempo, 140.);
+        assert_eq!(parallel_config.start, 2.);
+        for options in ["-s 2", "-o out.wav", "-S 1", "--start 1", "--lufs -14"] {
+            assert!(
+                Cli::try_parse_from(["test", "in.wav", "--parallel-chain", options])
+                    .unwrap()
+                    .parallel_config()
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn position_weight_shapes() {
+        assert_eq!(PositionWeight::Flat.at(0.3), 1.);
+        assert_eq!(PositionWeight::Bell.at(0.5), 1.);
+        assert!(PositionWeight::Bell.at(0.) < 0.05);
+        assert_eq!(PositionWeight::Edges.at(0.5), 0.);
+        assert!(PositionWeight::Edges.at(1.) > 0.95);
+        assert_eq!(PositionWeight::RampUp.at(0.25), 0.25);
+        assert_eq!(PositionWeight::RampDown.at(0.25), 0.75);
+    }
+
+    #[test]
+    fn config_detected_tempo() {
+        let cli = Cli::try_parse_from(["test", "in.wav", "--auto-tempo"]).unwrap();
+        assert!(cli.auto_tempo());
+        cli.set_detected_tempo(128.);
+        assert_eq!(cli.config().tempo, 128.);
+
+        let cli = Cli::try_parse_from(["test", "in.wav", "--auto-tempo", "-t", "90"]).unwrap();
+        assert!(!cli.auto_tempo());
+        cli.set_detected_tempo(128.);
+        assert_eq!(cli.config().tempo, 90.);
+    }
+
+    #[test]
+    fn timestamp_utc() {
+        assert_eq!(Cli::timestamp(0), "19700101T000000Z");
+        assert_eq!(Cli::timestamp(951_825_045), "20000229T115045Z");
+    }
+
+    #[test]
+    fn output_timestamp() {
+        let cli =
+            Cli::try_parse_from(["test", "in.wav", "-o", "dir/out.wav", "--timestamp"]).unwrap();
+        cli.started.set(951_825_045).unwrap();
+
+        assert_eq!(cli.output(), PathBuf::from("dir/out-20000229T115045Z.wav"));
+        assert_eq!(cli.meta(), [(*b"ICRD", "2000-02-29".to_string())]);
+    }
+
+    #[test]
+    fn meta_parser_no_eq() {
+        assert_eq!(
+            Cli::meta_parser("title"),
+            Err("metadata must be in key=value format".to_string())
+        );
+    }
+
+    #[test]
+    fn meta_parser_unknown_key() {
+        assert_eq!(
+            Cli::meta_parser("composer=x"),
+            Err("composer is not a known key or a four-letter ID".to_string())
+        );
+    }
+
+    #[test]
+    fn meta_parser_ok() {
+        assert_eq!(
+            Cli::meta_parser("title=a=b"),
+            Ok((*b"INAM", "a=b".to_string()))
+        );
+        assert_eq!(
+            Cli::meta_parser("IKEY=glitch"),
+            Ok((*b"IKEY", "glitch".to_string()))
+        );
     }
 
     #[test]
     fn probability_parser_not_float() {
         assert_eq!(
-            Cli::probability_parser(&"float"),
+            Cli::probability_parser("float"),
             Err("invalid float literal".to_string())
         );
     }
@@ -288,7 +2392,7 @@ mod tests {
     #[test]
     fn probability_parser_lesser() {
         assert_eq!(
-            Cli::probability_parser(&"-0.25"),
+            Cli::probability_parser("-0.25"),
             Err("-0.25 is not in 0.0..=1.0".to_string())
         );
     }
@@ -296,7 +2400,7 @@ mod tests {
     #[test]
     fn probability_parser_greater() {
         assert_eq!(
-            Cli::probability_parser(&"1.25"),
+            Cli::probability_parser("1.25"),
             Err("1.25 is not in 0.0..=1.0".to_string())
         );
     }
@@ -304,7 +2408,7 @@ mod tests {
     #[test]
     fn probability_parser_nan() {
         assert_eq!(
-            Cli::probability_parser(&"NaN"),
+            Cli::probability_parser("NaN"),
             Err("NaN is not in 0.0..=1.0".to_string())
         );
     }
@@ -312,14 +2416,52 @@ mod tests {
     #[test]
     fn probability_parser_inf() {
         assert_eq!(
-            Cli::probability_parser(&"inf"),
+            Cli::probability_parser("inf"),
             Err("inf is not in 0.0..=1.0".to_string())
         );
     }
 
     #[test]
     fn probability_parser_ok() {
-        assert_eq!(Cli::probability_parser(&"0.5"), Ok(0.5f64));
+        assert_eq!(Cli::probability_parser("0.5"), Ok(0.5f64));
+    }
+
+    #[test]
+    fn example_lines_all_effects() {
+        let examples = Cli::example_lines();
+
+        for effect in Effect::value_variants() {
+            let name = effect.to_possible_value().unwrap().get_name().to_string();
+            let line = examples
+                .lines()
+                .find(|line| line.contains(&format!("{name}.wav")))
+                .unwrap();
+            let args = line.trim_start().strip_prefix("$ ").unwrap();
+            let cli = Cli::try_parse_from(args.split(' ')).unwrap();
+            assert_eq!(cli.config().probability(*effect, 0.), 0.25);
+        }
+    }
+
+    #[test]
+    fn output_dir() {
+        let cli =
+            Cli::try_parse_from(["test", "samples/in.wav", "--output-dir", "processed"]).unwrap();
+
+        assert_eq!(cli.output(), PathBuf::from("processed/in-glitch.wav"));
+        assert!(!cli.defaults().contains("output path"));
+    }
+
+    #[test]
+    fn output_dir_conflicts_with_output() {
+        assert!(Cli::try_parse_from([
+            "test",
+            "in.wav",
+            "-o",
+            "out.wav",
+            "--output-dir",
+            "processed"
+        ])
+        .is_err());
     }
 
     #[test]
@@ -0,0 +1,108 @@
+use crate::cli::IntMath;
+use crate::sample_math::SampleMath;
+
+pub struct Compressor {
+    ratio: f64,
+    threshold: f64,
+    full_scale: f64,
+    attack: f64,
+    release: f64,
+    envelope: f64,
+    channels: usize,
+    bits: u16,
+    mode: IntMath,
+}
+
+impl Compressor {
+    const ATTACK: f64 = 0.005;
+    const RELEASE: f64 = 0.1;
+
+    /// Creates a compressor with threshold in dBFS, linked across channels
+    pub fn new(ratio: f64, threshold: f64, spec: hound::WavSpec, mode: IntMath) -> Compressor {
+        let coefficient = |time: f64| (-1. / (time * spec.sample_rate as f64)).exp();
+        let full_scale = match spec.sample_format {
+            hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f64,
+            hound::SampleFormat::Float => 1.,
+        };
+
+        Compressor {
+            ratio,
+            threshold: 10f64.powf(threshold / 20.),
+            full_scale,
+            attack: coefficient(Self::ATTACK),
+            release: coefficient(Self::RELEASE),
+            envelope: 0.,
+            channels: spec.channels as usize,
+            bits: spec.bits_per_sample,
+            mode,
+        }
+    }
+
+    pub fn process<S: SampleMath>(&mut self, samples: &mut [S]) {
+        for frame in samples.chunks_mut(self.channels) {
+            let peak = frame
+                .iter()
+                .map(|sample| sample.to_f64().abs() / self.full_scale)
+                .fold(0., f64::max);
+            let coefficient = if peak > self.envelope {
+                self.attack
+            } else {
+                self.release
+            };
+            self.envelope = coefficient * self.envelope + (1. - coefficient) * peak;
+
+            if self.envelope > self.threshold {
+                let gain = (self.envelope / self.threshold).powf(1. / self.ratio - 1.);
+                for sample in frame {
+                    *sample = S::from_f64_clamped(sample.to_f64() * gain, self.bits, self.mode);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(amplitude: f64, frames: usize) -> Vec<i32> {
+        (0..frames)
+            .flat_map(|i| {
+                let s = if i % 2 == 0 { amplitude } else { -amplitude } as i32;
+                [s, s]
+            })
+            .collect()
+    }
+
+    fn spec() -> hound::WavSpec {
+        hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        }
+    }
+
+    #[test]
+    fn compressor_reduces_by_ratio() {
+        // -6 dBFS into a 2:1 compressor at -12 dBFS settles at -9 dBFS
+        let amplitude = 32768. * 10f64.powf(-6. / 20.);
+        let mut samples = square(amplitude, 48000);
+        Compressor::new(2., -12., spec(), IntMath::Saturate).process(&mut samples);
+        let expected = 32768. * 10f64.powf(-9. / 20.);
+
+        assert!(samples[90000..]
+            .iter()
+            .all(|s| (s.abs() as f64 - expected).abs() < 0.02 * expected));
+    }
+
+    #[test]
+    fn compressor_keeps_quiet() {
+        let amplitude = 32768. * 10f64.powf(-18. / 20.);
+        let mut samples = square(amplitude, 4800);
+        let original = samples.clone();
+        Compressor::new(4., -12., spec(), IntMath::Saturate).process(&mut samples);
+
+        assert_eq!(samples, original);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::sample_math::SampleMath;
+
+/// Running mean of each channel, relative to full scale
+pub struct DcMeter {
+    sums: Vec<f64>,
+    frames: usize,
+    scale: f64,
+}
+
+impl DcMeter {
+    /// Offset above which the output is likely to have a problem (-40 dBFS)
+    pub const WARNING: f64 = 0.01;
+
+    pub fn new(spec: hound::WavSpec) -> DcMeter {
+        let scale = match spec.sample_format {
+            hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f64,
+            hound::SampleFormat::Float => 1.,
+        };
+
+        DcMeter {
+            sums: vec![0.; spec.channels as usize],
+            frames: 0,
+            scale,
+        }
+    }
+
+    pub fn push<S: SampleMath>(&mut self, samples: &[S]) {
+        for frame in samples.chunks(self.sums.len()) {
+            for (sum, sample) in self.sums.iter_mut().zip(frame) {
+                *sum += sample.to_f64() / self.scale;
+            }
+            self.frames += 1;
+        }
+    }
+
+    pub fn offsets(&self) -> Vec<f64> {
+        self.sums
+            .iter()
+            .map(|sum| sum / self.frames.max(1) as f64)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dc_meter_offsets() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut meter = DcMeter::new(spec);
+        meter.push(&[16384i32, 0, 0, -8192]);
+        meter.push(&[16384i32, 8192]);
+
+        assert_eq!(meter.offsets(), [1. / 3., 0.]);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::cli::IntMath;
+use crate::sample_math::SampleMath;
+
+pub struct Decorrelator {
+    channels: Vec<Allpass>,
+    bits: u16,
+    mode: IntMath,
+}
+
+struct Allpass {
+    buffer: Vec<f64>,
+    index: usize,
+    gain: f64,
+}
+
+impl Decorrelator {
+    const DELAYS: [f64; 2] = [0.0031, 0.0053];
+
+    pub fn new(amount: f64, sample_rate: u32, bits: u16, mode: IntMath) -> Decorrelator {
+        let channels = Self::DELAYS
+            .iter()
+            .map(|delay| Allpass::new((delay * sample_rate as f64) as usize, 0.7 * amount))
+            .collect();
+
+        Decorrelator {
+            channels,
+            bits,
+            mode,
+        }
+    }
+
+    pub fn process<S: SampleMath>(&mut self, samples: &mut [S]) {
+        for frame in samples.chunks_mut(self.channels.len()) {
+            for (sample, allpass) in frame.iter_mut().zip(&mut self.channels) {
+                let value = allpass.process(sample.to_f64());
+                *sample = S::from_f64_clamped(value, self.bits, self.mode);
+            }
+        }
+    }
+}
+
+impl Allpass {
+    fn new(delay: usize, gain: f64) -> Allpass {
+        Allpass {
+            buffer: vec![0.; delay.max(1)],
+            index: 0,
+            gain,
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let delayed = self.buffer[self.index];
+        let value = input + self.gain * delayed;
+        self.buffer[self.index] = value;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - self.gain * value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decorrelator_channels_differ() {
+        let mut samples: Vec<i32> = (0..4800)
+            .flat_map(|i| {
+                let s = if i < 2400 {
+                    (i * 7919) % 2001 - 1000
+                } else {
+                    0
+                };
+                [s, s]
+            })
+            .collect();
+        Decorrelator::new(1., 48000, 16, IntMath::Saturate).process(&mut samples);
+
+        let left: Vec<_> = samples.iter().step_by(2).collect();
+        let right: Vec<_> = samples.iter().skip(1).step_by(2).collect();
+
+        assert_ne!(left[..2400], right[..2400]);
+    }
+
+    #[test]
+    fn decorrelator_keeps_silence() {
+        let mut samples: Vec<i32> = (0..48000)
+            .flat_map(|i| {
+                let s = if i < 2400 {
+                    (i * 7919) % 2001 - 1000
+                } else {
+                    0
+                };
+                [s, s]
+            })
+            .collect();
+        Decorrelator::new(1., 48000, 16, IntMath::Saturate).process(&mut samples);
+
+        assert!(samples[24000 * 2..].iter().all(|s| s.abs() <= 1));
+    }
+}
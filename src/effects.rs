@@ -0,0 +1,439 @@
+use crate::cli::{FadeCurve, IntMath};
+use crate::sample_math::SampleMath;
+use rand::seq::SliceRandom;
+use rand::Rng;
+#[cfg(feature = "fft")]
+use rustfft::num_complex::Complex;
+#[cfg(feature = "fft")]
+use rustfft::FftPlanner;
+use std::f64::consts::PI;
+
+/// Holds the spectrum of the first grain of `channel` over its whole length, resynthesizing
+/// each half-overlapping grain from the same magnitudes with random phases
+#[cfg(feature = "fft")]
+pub fn freeze<S: SampleMath, R: Rng>(
+    channel: &mut [S],
+    grain_len: usize,
+    bits: u16,
+    mode: IntMath,
+    rng: &mut R,
+) {
+    let grain_len = grain_len.min(channel.len()) & !1;
+    if grain_len == 0 {
+        return;
+    }
+    let hop = grain_len / 2;
+    let mut planner = FftPlanner::new();
+    let fft = planner.plan_fft_forward(grain_len);
+    let ifft = planner.plan_fft_inverse(grain_len);
+    let mut frame: Vec<Complex<f64>> = channel[..grain_len]
+        .iter()
+        .enumerate()
+        .map(|(j, sample)| Complex::new(sample.to_f64() * hann(j, grain_len), 0.))
+        .collect();
+    fft.process(&mut frame);
+    let magnitudes: Vec<f64> = frame
+        .iter()
+        .map(|bin| bin.norm() / grain_len as f64)
+        .collect();
+    let mut output = vec![0.; channel.len() + hop + grain_len];
+    let mut position = 0;
+
+    while position < channel.len() + hop {
+        // Bins above the middle mirror those below so that the grain stays real
+        frame[0] = Complex::from(magnitudes[0]);
+        frame[hop] = Complex::from(magnitudes[hop]);
+        for k in 1..hop {
+            frame[k] = Complex::from_polar(magnitudes[k], rng.gen_range(0.0..2. * PI));
+            frame[grain_len - k] = frame[k].conj();
+        }
+        ifft.process(&mut frame);
+        for j in 0..grain_len {
+            output[position + j] += frame[j].re * hann(j, grain_len);
+        }
+        position += hop;
+    }
+
+    for (sample, value) in channel.iter_mut().zip(&output[hop..]) {
+        *sample = S::from_f64_clamped(*value, bits, mode);
+    }
+}
+
+/// Sustains the first two grains of `channel` over its whole length by overlapping grains
+/// drawn from them at random
+#[cfg(not(feature = "fft"))]
+pub fn freeze<S: SampleMath, R: Rng>(
+    channel: &mut [S],
+    grain_len: usize,
+    bits: u16,
+    mode: IntMath,
+    rng: &mut R,
+) {
+    let grain_len = grain_len.min(channel.len()) & !1;
+    if grain_len == 0 {
+        return;
+    }
+    let hop = grain_len / 2;
+    let source: Vec<f64> = channel[..(2 * grain_len).min(channel.len())]
+        .iter()
+        .map(|sample| sample.to_f64())
+        .collect();
+    let mut output = vec![0.; channel.len() + hop + grain_len];
+    let mut position = 0;
+
+    while position < channel.len() + hop {
+        let start = rng.gen_range(0..=source.len() - grain_len);
+        for j in 0..grain_len {
+            output[position + j] += source[start + j] * hann(j, grain_len);
+        }
+        position += hop;
+    }
+
+    for (sample, value) in channel.iter_mut().zip(&output[hop..]) {
+        *sample = S::from_f64_clamped(*value, bits, mode);
+    }
+}
+
+pub fn granular<S: SampleMath, R: Rng>(
+    channel: &mut [S],
+    grain_len: usize,
+    density: f64,
+    bits: u16,
+    mode: IntMath,
+    rng: &mut R,
+) {
+    let grain_len = grain_len.min(channel.len());
+    if grain_len == 0 {
+        return;
+    }
+    let hop = ((grain_len as f64 / density) as usize).max(1);
+    let source: Vec<f64> = channel.iter().map(|sample| sample.to_f64()).collect();
+    let mut output = vec![0.; channel.len() + grain_len];
+    let mut position = 0;
+
+    while position < channel.len() {
+        let start = rng.gen_range(0..=source.len() - grain_len);
+        for j in 0..grain_len {
+            output[position + j] += source[start + j] * hann(j, grain_len);
+        }
+        position += hop;
+    }
+
+    // Hann windows overlapping `grain_len / hop` times sum to about half that
+    let gain = 2. * hop as f64 / grain_len as f64;
+    for (sample, value) in channel.iter_mut().zip(&output) {
+        *sample = S::from_f64_clamped(value * gain, bits, mode);
+    }
+}
+
+pub fn remove_dc<S: SampleMath>(channel: &mut [S], bits: u16, mode: IntMath) {
+    if channel.is_empty() {
+        return;
+    }
+    let mean = channel.iter().map(|sample| sample.to_f64()).sum::<f64>() / channel.len() as f64;
+    for sample in channel {
+        *sample = S::from_f64_clamped(sample.to_f64() - mean, bits, mode);
+    }
+}
+
+/// Splits `channel` into `divisions` equal parts and plays them in shuffled order,
+/// leaving any remainder in place at the end
+pub fn beat_repeat<S: SampleMath, R: Rng>(channel: &mut [S], divisions: u8, rng: &mut R) {
+    let len = channel.len() / divisions as usize;
+    if len == 0 {
+        return;
+    }
+    let mut order: Vec<usize> = (0..divisions as usize).collect();
+    order.shuffle(rng);
+    let source = channel[..len * divisions as usize].to_vec();
+
+    for (target, &part) in channel.chunks_exact_mut(len).zip(&order) {
+        target.copy_from_slice(&source[part * len..(part + 1) * len]);
+    }
+}
+
+/// Pitches by `semitones` through linear interpolation, keeping the length by looping
+/// the channel when pitching up
+pub fn pitch<S: SampleMath>(channel: &mut [S], semitones: f64, bits: u16, mode: IntMath) {
+    let ratio = 2f64.powf(semitones / 12.);
+    let source: Vec<f64> = channel.iter().map(|sample| sample.to_f64()).collect();
+    let len = source.len();
+    for (i, sample) in channel.iter_mut().enumerate() {
+        let position = i as f64 * ratio;
+        let index = position as usize;
+        let fraction = position.fract();
+        let current = source[index % len];
+        let next = source[(index + 1) % len];
+        *sample = S::from_f64_clamped(current + (next - current) * fraction, bits, mode);
+    }
+}
+
+pub fn fade_in<S: SampleMath>(
+    channel: &mut [S],
+    fade_len: usize,
+    curve: FadeCurve,
+    bits: u16,
+    mode: IntMath,
+) {
+    let fade_len = fade_len.min(channel.len());
+    for (i, sample) in channel[..fade_len].iter_mut().enumerate() {
+        let gain = fade_gain(curve, i as f64 / fade_len as f64);
+        *sample = S::from_f64_clamped(sample.to_f64() * gain, bits, mode);
+    }
+}
+
+pub fn fade_out<S: SampleMath>(
+    channel: &mut [S],
+    fade_len: usize,
+    curve: FadeCurve,
+    bits: u16,
+    mode: IntMath,
+) {
+    let fade_len = fade_len.min(channel.len());
+    let start = channel.len() - fade_len;
+    for (i, sample) in channel[start..].iter_mut().rev().enumerate() {
+        let gain = fade_gain(curve, i as f64 / fade_len as f64);
+        *sample = S::from_f64_clamped(sample.to_f64() * gain, bits, mode);
+    }
+}
+
+/// Blends interleaved `samples` in from `tail`, the continuation of what came before them,
+/// over the length of `tail`
+pub fn crossfade<S: SampleMath>(
+    samples: &mut [S],
+    tail: &[S],
+    channels: usize,
+    bits: u16,
+    mode: IntMath,
+) {
+    let fade_len = (tail.len() / channels).min(samples.len() / channels);
+    for (i, (frame, tail)) in samples
+        .chunks_mut(channels)
+        .zip(tail.chunks(channels))
+        .take(fade_len)
+        .enumerate()
+    {
+        let gain = i as f64 / fade_len as f64;
+        for (sample, tail) in frame.iter_mut().zip(tail) {
+            let value = sample.to_f64() * gain + tail.to_f64() * (1. - gain);
+            *sample = S::from_f64_clamped(value, bits, mode);
+        }
+    }
+}
+
+/// Gain of a fade in at `position`, 0.0 to 1.0, through it
+fn fade_gain(curve: FadeCurve, position: f64) -> f64 {
+    match curve {
+        FadeCurve::Linear => position,
+        FadeCurve::EqualPower => (position * PI / 2.).sin(),
+    }
+}
+
+fn hann(index: usize, len: usize) -> f64 {
+    0.5 - 0.5 * (2. * PI * index as f64 / len as f64).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn block_rms(channel: &[f32], block_len: usize) -> Vec<f64> {
+        channel
+            .chunks(block_len)
+            .map(|block| {
+                (block.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / block.len() as f64).sqrt()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn freeze_keeps_length() {
+        let mut channel = vec![0.5f32; 1001];
+        freeze(
+            &mut channel,
+            64,
+            32,
+            IntMath::Saturate,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        assert_eq!(channel.len(), 1001);
+    }
+
+    #[test]
+    fn freeze_sustains() {
+        let mut channel: Vec<f32> = (0..4800)
+            .map(|i| {
+                if i < 256 {
+                    ((i * 37 % 19) as f32 - 9.) / 10.
+                } else {
+                    0.
+                }
+            })
+            .collect();
+        freeze(
+            &mut channel,
+            128,
+            32,
+            IntMath::Saturate,
+            &mut StdRng::seed_from_u64(0),
+        );
+        let rms = block_rms(&channel, 480);
+        let mean = rms.iter().sum::<f64>() / rms.len() as f64;
+
+        assert!(mean > 0.1);
+        assert!(rms.iter().all(|r| (r - mean).abs() < 0.25 * mean));
+    }
+
+    #[cfg(feature = "fft")]
+    #[test]
+    fn freeze_holds_spectrum() {
+        let mut channel: Vec<f32> = (0..4800)
+            .map(|i| {
+                if i < 256 {
+                    (2. * PI * i as f64 / 16.).sin() as f32 / 2.
+                } else {
+                    0.
+                }
+            })
+            .collect();
+        freeze(
+            &mut channel,
+            256,
+            32,
+            IntMath::Saturate,
+            &mut StdRng::seed_from_u64(0),
+        );
+        let crossings = channel
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.) != (pair[1] < 0.))
+            .count();
+
+        assert!((crossings as f64 / 600. - 1.).abs() < 0.1);
+    }
+
+    #[test]
+    fn beat_repeat_shuffles_divisions() {
+        let mut channel: Vec<i32> = (0..10).collect();
+        beat_repeat(&mut channel, 4, &mut StdRng::seed_from_u64(0));
+        let mut again: Vec<i32> = (0..10).collect();
+        beat_repeat(&mut again, 4, &mut StdRng::seed_from_u64(0));
+
+        assert_eq!(channel, again);
+        assert_eq!(channel[8..], [8, 9]);
+        let mut parts: Vec<&[i32]> = channel[..8].chunks(2).collect();
+        assert!(parts.iter().all(|part| part[1] == part[0] + 1));
+        parts.sort();
+        assert_eq!(parts.concat(), (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pitch_octaves() {
+        let mut up: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        pitch(&mut up, 12., 32, IntMath::Saturate);
+        assert_eq!(up, [0., 2., 4., 6., 0., 2., 4., 6.]);
+
+        let mut down: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        pitch(&mut down, -12., 32, IntMath::Saturate);
+        assert_eq!(down, [0., 0.5, 1., 1.5, 2., 2.5, 3., 3.5]);
+    }
+
+    #[test]
+    fn fade_ramps() {
+        let mut channel = vec![1f32; 8];
+        fade_in(&mut channel, 4, FadeCurve::Linear, 32, IntMath::Saturate);
+        assert_eq!(channel, [0., 0.25, 0.5, 0.75, 1., 1., 1., 1.]);
+        fade_out(&mut channel, 2, FadeCurve::Linear, 32, IntMath::Saturate);
+        assert_eq!(channel, [0., 0.25, 0.5, 0.75, 1., 1., 0.5, 0.]);
+    }
+
+    #[test]
+    fn crossfade_blends() {
+        let mut samples = vec![1f32, -1., 1., -1., 1., -1.];
+        crossfade(&mut samples, &[0., 0., 0.5, 0.5], 2, 32, IntMath::Saturate);
+        assert_eq!(samples, [0., 0., 0.75, -0.25, 1., -1.]);
+    }
+
+    #[test]
+    fn fade_equal_power() {
+        let energy = |curve, position: f64| {
+            fade_gain(curve, position).powi(2) + fade_gain(curve, 1. - position).powi(2)
+        };
+
+        assert_eq!(energy(FadeCurve::Linear, 0.5), 0.5);
+        for position in [0., 0.25, 0.5, 0.75] {
+            assert!((energy(FadeCurve::EqualPower, position) - 1.).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn remove_dc_clamps() {
+        let mut channel = vec![-32768i32, -32768, -32768, 32767];
+        remove_dc(&mut channel, 16, IntMath::Saturate);
+
+        assert_eq!(channel, [-16384, -16384, -16384, 32767]);
+    }
+
+    #[test]
+    fn remove_dc_wraps() {
+        let mut channel = vec![-32768i32, -32768, -32768, 32767];
+        remove_dc(&mut channel, 16, IntMath::Wrap);
+
+        assert_eq!(channel, [-16384, -16384, -16384, -16385]);
+    }
+
+    #[test]
+    fn granular_keeps_length() {
+        let mut channel = vec![0.5f32; 1001];
+        granular(
+            &mut channel,
+            64,
+            4.,
+            16,
+            IntMath::Saturate,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        assert_eq!(channel.len(), 1001);
+    }
+
+    #[test]
+    fn granular_deterministic() {
+        let source: Vec<f32> = (0..4800)
+            .map(|i| ((i * 37 % 19) as f32 - 9.) / 10.)
+            .collect();
+        let mut first = source.clone();
+        let mut second = source.clone();
+        let mut other = source.clone();
+        granular(
+            &mut first,
+            240,
+            4.,
+            16,
+            IntMath::Saturate,
+            &mut StdRng::seed_from_u64(7),
+        );
+        granular(
+            &mut second,
+            240,
+            4.,
+            16,
+            IntMath::Saturate,
+            &mut StdRng::seed_from_u64(7),
+        );
+        granular(
+            &mut other,
+            240,
+            4.,
+            16,
+            IntMath::Saturate,
+            &mut StdRng::seed_from_u64(8),
+        );
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_ne!(first, source);
+    }
+}
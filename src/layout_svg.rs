@@ -0,0 +1,101 @@
+use crate::segment_layout::{Segment, SegmentLayout};
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+const WIDTH: f64 = 1000.;
+const ROW_HEIGHT: f64 = 24.;
+
+/// Renders `layout` as a timeline with one row of segments per channel, colored by effect
+pub fn render(layout: &SegmentLayout) -> String {
+    let slots = layout.slots();
+    let start = slots.first().map_or(0, |&(offset, _)| offset);
+    let end = slots
+        .last()
+        .map_or(start, |&(offset, len)| offset + len as u32);
+    let scale = WIDTH / (end - start).max(1) as f64;
+    let height = ROW_HEIGHT * layout.channels().len() as f64;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{height}\" \
+         viewBox=\"0 0 {WIDTH} {height}\">\n"
+    );
+    for (row, channel) in layout.channels().iter().enumerate() {
+        for (segment, &(offset, len)) in channel.iter().zip(slots) {
+            let (effect, color) = style(segment, offset);
+            // Writing into a String cannot fail
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"{color}\" \
+                 stroke=\"#fff\" stroke-width=\"0.5\"><title>{offset}: {effect}</title></rect>",
+                (offset - start) as f64 * scale,
+                row as f64 * ROW_HEIGHT,
+                len as f64 * scale,
+                ROW_HEIGHT,
+            );
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn write<P: AsRef<Path>>(layout: &SegmentLayout, path: P) -> Result<()> {
+    fs::write(path, render(layout)).context("when writing layout SVG")
+}
+
+/// Name and color of the most audible effect of a segment in the slot at `offset`
+fn style(segment: &Segment, offset: u32) -> (&'static str, &'static str) {
+    if segment.silence() {
+        ("silenced", "#333333")
+    } else if segment.reverse() {
+        ("reversed", "#d64541")
+    } else if segment.freeze() {
+        ("frozen", "#4ab8d8")
+    } else if segment.granular() {
+        ("granular", "#9b59b6")
+    } else if segment.beat_repeat() {
+        ("beat repeated", "#e6a23c")
+    } else if segment.offset() != offset {
+        ("moved", "#3f7fd1")
+    } else if segment.invert() {
+        ("widened", "#5cb85c")
+    } else if segment.gain().is_some() {
+        ("gained", "#c0a060")
+    } else {
+        ("clean", "#d9d9d9")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CliConfig;
+    use crate::wav_reader::WavConfig;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn render_rectangles() {
+        let cli_config = CliConfig {
+            prob_reverse: 0.5,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let layout = SegmentLayout::build(
+            cli_config,
+            wav_config,
+            &[],
+            &[],
+            &mut StdRng::seed_from_u64(0),
+        );
+        let svg = render(&layout);
+
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<rect").count(), 2 * 7);
+    }
+}
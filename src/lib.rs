@@ -1,5 +1,20 @@
+pub mod analysis;
+pub mod channel_delay;
 pub mod cli;
+pub mod compressor;
+pub mod dc_meter;
+pub mod decorrelator;
+pub mod effects;
+pub mod layout_svg;
+pub mod loudness;
+pub mod onsets;
+pub mod peaks;
+pub mod resources;
+#[cfg(feature = "fft")]
+pub mod reverb;
 pub mod run;
+pub mod run_log;
+pub mod sample_math;
 pub mod segment_layout;
 pub mod wav_reader;
 pub mod wav_writer;
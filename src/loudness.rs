@@ -0,0 +1,246 @@
+use crate::cli::PeakMode;
+use std::f64::consts::PI;
+
+/// Length in seconds of a gating block
+const BLOCK: f64 = 0.4;
+/// Step in seconds between gating blocks (75% overlap)
+const STEP: f64 = 0.1;
+const ABSOLUTE_GATE: f64 = -70.;
+const RELATIVE_GATE: f64 = -10.;
+/// Peak ceiling of the safety limiter (-1 dBFS)
+const CEILING: f64 = 0.891;
+const RELEASE: f64 = 0.05;
+/// Oversampling factor of true peak measurement
+const OVERSAMPLING: usize = 4;
+/// Samples on each side of the windowed sinc interpolating between samples
+const TAPS: isize = 8;
+
+/// Integrated loudness in LUFS after ITU-R BS.1770 of interleaved samples in -1.0..=1.0,
+/// weighting all channels equally
+pub fn integrated(samples: &[f64], channels: usize, sample_rate: u32) -> f64 {
+    let frames = samples.len() / channels;
+    let mut squares = vec![0.; frames];
+    for channel in 0..channels {
+        let mut filters = k_weighting(sample_rate as f64);
+        for (square, frame) in squares.iter_mut().zip(samples.chunks(channels)) {
+            let weighted = filters
+                .iter_mut()
+                .fold(frame[channel], |x, filter| filter.process(x));
+            *square += weighted * weighted;
+        }
+    }
+
+    let block = ((BLOCK * sample_rate as f64) as usize).min(frames).max(1);
+    let step = ((STEP * sample_rate as f64) as usize).max(1);
+    let powers: Vec<f64> = (0..=frames.saturating_sub(block))
+        .step_by(step)
+        .map(|start| squares[start..start + block].iter().sum::<f64>() / block as f64)
+        .filter(|&power| loudness(power) > ABSOLUTE_GATE)
+        .collect();
+    if powers.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+
+    let threshold = loudness(mean(&powers)) + RELATIVE_GATE;
+    let gated: Vec<f64> = powers
+        .into_iter()
+        .filter(|&power| loudness(power) > threshold)
+        .collect();
+    loudness(mean(&gated))
+}
+
+/// Applies a single gain bringing interleaved samples in -1.0..=1.0 to `target` LUFS, then
+/// limits peaks to -1 dBFS. Silent input is left unchanged.
+pub fn normalize(
+    samples: &mut [f64],
+    channels: usize,
+    sample_rate: u32,
+    target: f64,
+    mode: PeakMode,
+) {
+    let current = integrated(samples, channels, sample_rate);
+    if !current.is_finite() {
+        return;
+    }
+    let gain = 10f64.powf((target - current) / 20.);
+    for sample in samples.iter_mut() {
+        *sample *= gain;
+    }
+    limit(samples, channels, sample_rate, mode);
+}
+
+/// Highest peak of interleaved samples
+pub fn peak(samples: &[f64], channels: usize, mode: PeakMode) -> f64 {
+    frame_peaks(samples, channels, mode)
+        .into_iter()
+        .fold(0., f64::max)
+}
+
+/// Peak of each frame across channels, including with `PeakMode::True` the peaks between
+/// it and the next frame, found by oversampling
+fn frame_peaks(samples: &[f64], channels: usize, mode: PeakMode) -> Vec<f64> {
+    let mut peaks: Vec<f64> = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().map(|x| x.abs()).fold(0., f64::max))
+        .collect();
+    if mode == PeakMode::True {
+        for channel in 0..channels {
+            let signal: Vec<f64> = samples
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .copied()
+                .collect();
+            for (index, peak) in peaks.iter_mut().enumerate() {
+                for phase in 1..OVERSAMPLING {
+                    let value = interpolate(&signal, index, phase as f64 / OVERSAMPLING as f64);
+                    *peak = peak.max(value.abs());
+                }
+            }
+        }
+    }
+    peaks
+}
+
+/// Value of `signal` at `fraction` of the way from `index` to the next sample, through a
+/// Hann-windowed sinc
+fn interpolate(signal: &[f64], index: usize, fraction: f64) -> f64 {
+    (1 - TAPS..=TAPS)
+        .filter_map(|k| {
+            let sample = signal.get(usize::try_from(index as isize + k).ok()?)?;
+            let t = k as f64 - fraction;
+            let sinc = (PI * t).sin() / (PI * t);
+            let window = 0.5 + 0.5 * (PI * t / TAPS as f64).cos();
+            Some(sample * sinc * window)
+        })
+        .sum()
+}
+
+/// Peak limiter linked across channels, with instant attack so that no peak exceeds
+/// the ceiling
+fn limit(samples: &mut [f64], channels: usize, sample_rate: u32, mode: PeakMode) {
+    let release = (-1. / (RELEASE * sample_rate as f64)).exp();
+    let peaks = frame_peaks(samples, channels, mode);
+    let mut gain = 1f64;
+    for (frame, &peak) in samples.chunks_mut(channels).zip(&peaks) {
+        let required = if peak > CEILING { CEILING / peak } else { 1. };
+        gain = required.min(release * gain + (1. - release));
+        for sample in frame {
+            *sample *= gain;
+        }
+    }
+}
+
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10. * power.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Biquad filter in direct form I
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// High shelf and high pass stages of the K-weighting filter at any sample rate
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let k = (std::f64::consts::PI * 1681.974450955533 / sample_rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        x: [0.; 2],
+        y: [0.; 2],
+    };
+
+    let k = (std::f64::consts::PI * 38.13547087602444 / sample_rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1., -2., 1.],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        x: [0.; 2],
+        y: [0.; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f64, frames: usize) -> Vec<f64> {
+        (0..frames)
+            .map(|i| amplitude * (2. * std::f64::consts::PI * 997. * i as f64 / 48000.).sin())
+            .collect()
+    }
+
+    #[test]
+    fn integrated_sine() {
+        // A full scale 997 Hz sine in one channel measures -3.01 LUFS
+        let loudness = integrated(&sine(0.1, 96000), 1, 48000);
+        assert!((loudness + 23.01).abs() < 0.05, "{loudness}");
+    }
+
+    #[test]
+    fn integrated_silence() {
+        assert_eq!(integrated(&[0.; 96000], 1, 48000), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn normalize_target() {
+        let mut samples = sine(0.01, 96000);
+        normalize(&mut samples, 1, 48000, -14., PeakMode::Sample);
+
+        let loudness = integrated(&samples, 1, 48000);
+        assert!((loudness + 14.).abs() < 0.05, "{loudness}");
+    }
+
+    #[test]
+    fn normalize_limits_peaks() {
+        let mut samples: Vec<f64> = sine(0.5, 96000)
+            .into_iter()
+            .flat_map(|x| [x, x / 2.])
+            .collect();
+        normalize(&mut samples, 2, 48000, 0., PeakMode::Sample);
+
+        assert!(samples.iter().all(|x| x.abs() <= CEILING + 1e-12));
+    }
+
+    #[test]
+    fn true_peak_between_samples() {
+        // A quarter sample rate sine at 45 degrees peaks halfway between samples
+        let samples: Vec<f64> = (0..4800)
+            .map(|i| (PI / 2. * i as f64 + PI / 4.).sin())
+            .collect();
+        let sample_peak = peak(&samples, 1, PeakMode::Sample);
+        let true_peak = peak(&samples, 1, PeakMode::True);
+
+        assert!((sample_peak - 0.5f64.sqrt()).abs() < 1e-9, "{sample_peak}");
+        assert!((true_peak - 1.).abs() < 0.02, "{true_peak}");
+    }
+}
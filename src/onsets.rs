@@ -0,0 +1,100 @@
+/// Rise in frame energy (natural log, about 6 dB) that counts as an onset
+const RISE: f64 = 1.386;
+/// Frame energy below which nothing is considered an onset (about -60 dBFS)
+const FLOOR: f64 = 1e-6;
+const HOP: f64 = 0.01;
+const MIN_GAP: f64 = 0.05;
+/// Lowest tempo estimated, the highest being twice as much
+const MIN_TEMPO: f64 = 80.;
+
+/// Detects onsets as sudden rises in frame energy, returning their positions in samples
+pub fn detect(channels: &[Vec<f64>], sample_rate: u32) -> Vec<u32> {
+    let hop = ((sample_rate as f64 * HOP) as usize).max(1);
+    let min_gap = (sample_rate as f64 * MIN_GAP) as usize;
+    let len = channels.first().map_or(0, Vec::len);
+    let mut onsets = vec![];
+    let mut previous = FLOOR;
+    let mut last = None;
+
+    for frame in (0..len).step_by(hop) {
+        let end = (frame + hop).min(len);
+        let energy = channels
+            .iter()
+            .flat_map(|channel| &channel[frame..end])
+            .map(|x| x * x)
+            .sum::<f64>()
+            / ((end - frame) * channels.len()) as f64;
+
+        if energy > FLOOR
+            && (energy / previous).ln() > RISE
+            && last.is_none_or(|last| frame - last >= min_gap)
+        {
+            onsets.push(frame as u32);
+            last = Some(frame);
+        }
+        previous = energy.max(FLOOR);
+    }
+
+    onsets
+}
+
+/// Estimates the tempo in BPM from the typical interval between `onsets`, folded by octaves
+/// into 80 to 160 BPM, or nothing without two onsets
+pub fn tempo(onsets: &[u32], sample_rate: u32) -> Option<f64> {
+    let mut intervals: Vec<f64> = onsets
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]) as f64)
+        .collect();
+    intervals.sort_by(f64::total_cmp);
+    let median = *intervals.get(intervals.len() / 2)?;
+    // Average the intervals near the median to smooth out the quantization to hops
+    let near: Vec<f64> = intervals
+        .into_iter()
+        .filter(|interval| (interval / median - 1.).abs() < 0.1)
+        .collect();
+    let interval = near.iter().sum::<f64>() / near.len() as f64;
+
+    let mut tempo = 60. * sample_rate as f64 / interval;
+    while tempo < MIN_TEMPO {
+        tempo *= 2.;
+    }
+    while tempo >= 2. * MIN_TEMPO {
+        tempo /= 2.;
+    }
+    Some(tempo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_bursts() {
+        let mut channel = vec![0.; 48000];
+        for burst in [12000, 24000, 36100] {
+            for i in 0..4800 {
+                channel[burst + i] = 0.5 * (i as f64 * 0.3).sin() * (-(i as f64) / 1200.).exp();
+            }
+        }
+        let onsets = detect(&[channel.clone(), channel], 48000);
+
+        assert_eq!(onsets.len(), 3);
+        for (onset, burst) in onsets.into_iter().zip([12000, 24000, 36100]) {
+            assert!(onset.abs_diff(burst) < 480);
+        }
+    }
+
+    #[test]
+    fn tempo_intervals() {
+        assert_eq!(tempo(&[0, 24000, 48000, 72000], 48000), Some(120.));
+        // Quarter notes at 60 BPM fold up, eighth notes at 300 BPM fold down
+        assert_eq!(tempo(&[0, 48000, 96000], 48000), Some(120.));
+        assert_eq!(tempo(&[0, 9600, 19200], 48000), Some(150.));
+        assert_eq!(tempo(&[0], 48000), None);
+    }
+
+    #[test]
+    fn detect_silence() {
+        assert!(detect(&[vec![0.; 48000]], 48000).is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::sample_math::SampleMath;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(Serialize)]
+pub struct Peaks {
+    channels: usize,
+    block_len: usize,
+    peaks: Vec<Vec<[f32; 2]>>,
+    #[serde(skip)]
+    frames: usize,
+    #[serde(skip)]
+    scale: f64,
+}
+
+impl Peaks {
+    pub const BLOCK_LEN: usize = 512;
+
+    pub fn new(spec: hound::WavSpec) -> Peaks {
+        let scale = match spec.sample_format {
+            hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f64,
+            hound::SampleFormat::Float => 1.,
+        };
+
+        Peaks {
+            channels: spec.channels as usize,
+            block_len: Self::BLOCK_LEN,
+            peaks: vec![vec![]; spec.channels as usize],
+            frames: 0,
+            scale,
+        }
+    }
+
+    pub fn push<S: SampleMath>(&mut self, samples: &[S]) {
+        for frame in samples.chunks(self.channels) {
+            for (channel, sample) in self.peaks.iter_mut().zip(frame) {
+                let value = (sample.to_f64() / self.scale) as f32;
+                if self.frames.is_multiple_of(self.block_len) {
+                    channel.push([value, value]);
+                } else if let Some(peak) = channel.last_mut() {
+                    peak[0] = peak[0].min(value);
+                    peak[1] = peak[1].max(value);
+                }
+            }
+            self.frames += 1;
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path).context("when creating peaks file")?;
+        serde_json::to_writer(BufWriter::new(file), self).context("when writing peaks file")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peaks_blocks() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut peaks = Peaks::new(spec);
+        let samples: Vec<i32> = (0..1100).flat_map(|i| [i, -i]).collect();
+        peaks.push(&samples[..600]);
+        peaks.push(&samples[600..]);
+
+        assert_eq!(peaks.peaks[0].len(), 3);
+        assert_eq!(peaks.peaks[1].len(), 3);
+        assert_eq!(peaks.peaks[0][1], [512. / 32768., 1023. / 32768.]);
+        assert_eq!(peaks.peaks[1][2], [-1099. / 32768., -1024. / 32768.]);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::wav_reader::WavConfig;
+use std::mem::size_of;
+
+/// Samples processed per second by a release build, as reported by `--benchmark`
+const THROUGHPUT: f64 = 2e7;
+
+#[derive(Debug, PartialEq)]
+pub struct Estimate {
+    /// Peak memory in bytes
+    pub memory: u64,
+    pub seconds: f64,
+}
+
+/// Rough cost of processing an input of `wav_config`, with the whole input read up front
+/// when `whole_input` and the output held until written when `buffered_output`
+pub fn estimate(wav_config: WavConfig, whole_input: bool, buffered_output: bool) -> Estimate {
+    let channels = wav_config.channels as u64;
+    let samples = wav_config.duration as u64 * channels;
+    let sample_size = size_of::<f64>() as u64;
+
+    // About a second of working buffers for segments and output stages
+    let mut memory = wav_config.sample_rate as u64 * channels * sample_size;
+    if whole_input {
+        memory += samples * sample_size;
+    }
+    if buffered_output {
+        memory += samples * sample_size;
+    }
+    let passes = if whole_input { 2. } else { 1. };
+
+    Estimate {
+        memory,
+        seconds: passes * samples as f64 / THROUGHPUT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_scales() {
+        let wav_config = WavConfig {
+            duration: 480000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let base = estimate(wav_config, true, true);
+        let longer = estimate(
+            WavConfig {
+                duration: 960000,
+                ..wav_config
+            },
+            true,
+            true,
+        );
+        let wider = estimate(
+            WavConfig {
+                channels: 4,
+                ..wav_config
+            },
+            true,
+            true,
+        );
+
+        assert!(longer.memory > base.memory);
+        assert_eq!(longer.seconds, 2. * base.seconds);
+        assert_eq!(wider.memory, 2 * base.memory);
+        assert_eq!(wider.seconds, 2. * base.seconds);
+        assert!(estimate(wav_config, false, false).memory < base.memory);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::cli::IntMath;
+use crate::sample_math::SampleMath;
+use anyhow::{anyhow, Result};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+pub struct Reverb {
+    block_len: usize,
+    fft: Arc<dyn Fft<f64>>,
+    ifft: Arc<dyn Fft<f64>>,
+    channels: Vec<Convolver>,
+    bits: u16,
+    mode: IntMath,
+    tail_len: usize,
+    input_frames: usize,
+    output_frames: usize,
+}
+
+struct Convolver {
+    partitions: Vec<Vec<Complex<f64>>>,
+    spectra: VecDeque<Vec<Complex<f64>>>,
+    previous: Vec<f64>,
+    current: Vec<f64>,
+    ready: VecDeque<f64>,
+}
+
+impl Reverb {
+    const BLOCK_LEN: usize = 1024;
+
+    pub fn new(
+        impulse: Vec<Vec<f64>>,
+        channels: usize,
+        bits: u16,
+        mode: IntMath,
+    ) -> Result<Reverb> {
+        if impulse.len() != 1 && impulse.len() != channels {
+            return Err(anyhow!(
+                "impulse response must be mono or have {channels} channels"
+            ));
+        }
+        let block_len = Self::BLOCK_LEN;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(2 * block_len);
+        let ifft = planner.plan_fft_inverse(2 * block_len);
+        let tail_len = impulse[0].len().saturating_sub(1);
+        let channels = (0..channels)
+            .map(|i| Convolver::new(&impulse[i % impulse.len()], block_len, fft.as_ref()))
+            .collect();
+
+        Ok(Reverb {
+            block_len,
+            fft,
+            ifft,
+            channels,
+            bits,
+            mode,
+            tail_len,
+            input_frames: 0,
+            output_frames: 0,
+        })
+    }
+
+    pub fn process<S: SampleMath>(&mut self, samples: &[S]) -> Vec<S> {
+        let channel_count = self.channels.len();
+        for frame in samples.chunks(channel_count) {
+            for (convolver, sample) in self.channels.iter_mut().zip(frame) {
+                convolver.push(sample.to_f64(), self.block_len, &*self.fft, &*self.ifft);
+            }
+        }
+        self.input_frames += samples.len() / channel_count;
+        self.drain(usize::MAX)
+    }
+
+    pub fn flush<S: SampleMath>(&mut self) -> Vec<S> {
+        let total = self.input_frames + self.tail_len;
+        while self.output_frames + self.channels[0].ready.len() < total {
+            for convolver in &mut self.channels {
+                convolver.push(0., self.block_len, &*self.fft, &*self.ifft);
+            }
+        }
+        self.drain(total - self.output_frames)
+    }
+
+    fn drain<S: SampleMath>(&mut self, max_frames: usize) -> Vec<S> {
+        let frames = self.channels[0].ready.len().min(max_frames);
+        let mut samples = Vec::with_capacity(frames * self.channels.len());
+        for _ in 0..frames {
+            for convolver in &mut self.channels {
+                let value = convolver.ready.pop_front().unwrap_or(0.);
+                samples.push(S::from_f64_clamped(value, self.bits, self.mode));
+            }
+        }
+        self.output_frames += frames;
+        samples
+    }
+}
+
+impl Convolver {
+    fn new(impulse: &[f64], block_len: usize, fft: &dyn Fft<f64>) -> Convolver {
+        let partitions: Vec<_> = impulse
+            .chunks(block_len)
+            .map(|chunk| {
+                let mut buffer = vec![Complex::default(); 2 * block_len];
+                for (value, sample) in buffer.iter_mut().zip(chunk) {
+                    value.re = *sample;
+                }
+                fft.process(&mut buffer);
+                buffer
+            })
+            .collect();
+        let spectra = VecDeque::from(vec![
+            vec![Complex::default(); 2 * block_len];
+            partitions.len()
+        ]);
+
+        Convolver {
+            partitions,
+            spectra,
+            previous: vec![0.; block_len],
+            current: Vec::with_capacity(block_len),
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, sample: f64, block_len: usize, fft: &dyn Fft<f64>, ifft: &dyn Fft<f64>) {
+        self.current.push(sample);
+        if self.current.len() < block_len {
+            return;
+        }
+
+        let mut buffer: Vec<Complex<f64>> = self
+            .previous
+            .iter()
+            .chain(&self.current)
+            .map(|sample| Complex::new(*sample, 0.))
+            .collect();
+        fft.process(&mut buffer);
+        self.spectra.pop_back();
+        self.spectra.push_front(buffer);
+
+        let mut output = vec![Complex::default(); 2 * block_len];
+        for (spectrum, partition) in self.spectra.iter().zip(&self.partitions) {
+            for ((out, x), h) in output.iter_mut().zip(spectrum).zip(partition) {
+                *out += x * h;
+            }
+        }
+        ifft.process(&mut output);
+
+        let scale = 1. / (2 * block_len) as f64;
+        self.ready
+            .extend(output[block_len..].iter().map(|value| value.re * scale));
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverb_impulse() {
+        let impulse: Vec<f64> = (0..3000).map(|i| ((i * 13 % 7) as f64 - 3.) / 4.).collect();
+        let mut reverb = Reverb::new(vec![impulse.clone()], 1, 32, IntMath::Saturate).unwrap();
+        let mut input = vec![0f32; 100];
+        input[0] = 1.;
+        let mut output = reverb.process(&input);
+        output.extend(reverb.flush::<f32>());
+
+        assert_eq!(output.len(), 100 + 2999);
+        for (out, expected) in output.iter().zip(&impulse) {
+            assert!((*out as f64 - expected).abs() < 1e-5);
+        }
+        assert!(output[3000..].iter().all(|out| out.abs() < 1e-5));
+    }
+
+    #[test]
+    fn reverb_channel_mismatch() {
+        assert!(Reverb::new(vec![vec![1.], vec![1.], vec![1.]], 2, 16, IntMath::Saturate).is_err());
+    }
+}
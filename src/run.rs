@@ -1,41 +1,822 @@
-use crate::cli::Cli;
+use crate::analysis::Analysis;
+use crate::channel_delay::ChannelDelay;
+use crate::cli::{Cli, CliConfig};
+use crate::compressor::Compressor;
+use crate::dc_meter::DcMeter;
+use crate::decorrelator::Decorrelator;
+use crate::effects;
+use crate::layout_svg;
+use crate::onsets;
+use crate::peaks::Peaks;
+use crate::resources;
+#[cfg(feature = "fft")]
+use crate::reverb::Reverb;
+use crate::run_log::RunLog;
+use crate::sample_math::SampleMath;
 use crate::segment_layout::SegmentLayout;
 use crate::wav_reader::WavReader;
-use crate::wav_writer::WavWriter;
-use anyhow::{anyhow, Result};
-use std::io::{self, Write};
-use yansi::Condition;
+use crate::wav_writer::{BufferWriter, NullWriter, SampleWriter, WavWriter};
+use anyhow::{anyhow, Context, Result};
+use base64::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::time::Instant;
+use yansi::{Condition, Paint};
+
+/// Number of layouts built with `--ensure-change` before giving up
+const CHANGE_ATTEMPTS: usize = 8;
+/// Size in bytes of an output beyond which printing it as base64 is unwieldy
+const BASE64_WARNING: usize = 1_000_000;
+/// Length in seconds of the silence between previews of `--seed-sweep`
+const SWEEP_GAP: f64 = 0.5;
 
 pub fn run(cli: Cli) -> Result<()> {
-    if cli.input() == cli.output() {
+    if cli.examples() {
+        println!("{}", Cli::example_lines());
+        return Ok(());
+    }
+
+    if cli.validate_only() {
+        WavReader::open_with(cli.input(), cli.read_mode())?.validate()?;
+        println!("{}: OK", cli.input().display());
+        return Ok(());
+    }
+
+    let started = Instant::now();
+
+    let inputs = [&[cli.input()], cli.concat().as_slice()].concat();
+    if inputs.contains(&cli.output())
+        || cli
+            .intermediate()
+            .is_some_and(|path| inputs.contains(&path))
+    {
         return Err(anyhow!("input path is the same as output path"));
     }
 
-    let mut reader = WavReader::open(cli.input())?;
-    let mut writer = WavWriter::create(cli.output(), reader.spec())?;
+    let reader = WavReader::open_with(cli.input(), cli.read_mode())?;
+    if reader.spec().channels > cli.max_channels() {
+        return Err(anyhow!(
+            "input has {} channels, more than the maximum of {}",
+            reader.spec().channels,
+            cli.max_channels()
+        ));
+    }
+    let mut reader = reader
+        .with_concat(&cli.concat(), cli.read_mode())?
+        .with_merge(cli.merge())?;
+    if let Some(path) = cli.matrix() {
+        reader = reader.with_matrix(WavReader::read_matrix(path)?)?;
+    }
+    let seed = cli.seed().unwrap_or_else(rand::random);
+    let mut rng = StdRng::seed_from_u64(seed);
+    reader = reader.with_order(cli.channel_order())?.with_seed(rng.gen());
+    if cli.check_input_clipping() {
+        let clipped = reader.clipped()?;
+        print_report(&cli, format!("Input samples at full scale: {clipped}"));
+        if clipped > 0 {
+            eprintln!(
+                "{} input has samples at full scale and may be clipping",
+                "Warning:".bold().bright().yellow()
+            );
+        }
+    }
+    if cli.auto_tempo() {
+        let onsets = onsets::detect(&reader.read_all()?, reader.spec().sample_rate);
+        let tempo = onsets::tempo(&onsets, reader.spec().sample_rate)
+            .ok_or(anyhow!("could not detect a tempo in the input"))?;
+        print_report(&cli, format!("Detected tempo: {tempo:.1} BPM"));
+        cli.set_detected_tempo(tempo);
+    }
+
+    // Progress would mix into the base64 output on stdout
+    let cli_config = CliConfig {
+        quiet: cli.config().quiet || cli.base64(),
+        ..cli.config()
+    };
+    let parallel_config = cli
+        .parallel_config()
+        .context("when parsing parallel chain options")?;
+
+    for config in [Some(cli_config), parallel_config].into_iter().flatten() {
+        check(config, &reader)?;
+    }
+
+    let warnings = cli.warnings(reader.config());
+    for warning in &warnings {
+        if cli.strict() {
+            return Err(anyhow!(warning.clone()));
+        }
+        eprintln!("{} {warning}", "Warning:".bold().bright().yellow());
+    }
+
+    let mut stages = Stages::new(&cli, reader.spec())?;
+
+    if !cli_config.quiet {
+        eprintln!("{}", cli.defaults());
+        if cli.seed().is_none() && cli.seed_sweep().is_none() {
+            eprintln!(
+                "Using random seed {}, pass it with -S to reproduce this output",
+                cli.seed_format().format(seed)
+            );
+        }
+    }
+
+    if cli.resource_report() {
+        let estimate = resources::estimate(
+            reader.config(),
+            cli.transient_segments(),
+            cli.buffered_output(),
+        );
+        print_report(
+            &cli,
+            format!(
+                "Estimated peak memory: {:.1} MB, processing time: {:.1} s",
+                estimate.memory as f64 / 1e6,
+                estimate.seconds
+            ),
+        );
+    }
+
+    if let Some(count) = cli.seed_variance() {
+        let layouts = (0..count)
+            .map(|_| build_layout(&cli, cli_config, &mut reader, &mut rng))
+            .collect::<Result<Vec<_>>>()?;
+        println!(
+            "Seed variance: {:.1}% of segments differ between {count} seeds",
+            100. * SegmentLayout::variance(&layouts)
+        );
+        return Ok(());
+    }
+
+    if let Some(dir) = cli.output_dir() {
+        fs::create_dir_all(dir).context("when creating output directory")?;
+    }
+
+    if let Some((first, last, preview)) = cli.seed_sweep() {
+        return sweep(&cli, cli_config, reader, first..=last, preview);
+    }
+
+    let layout = match cli.layout_cache() {
+        Some(path) if path.exists() => SegmentLayout::from_file(path, reader.config())?,
+        cache => {
+            let mut layout = build_layout(&cli, cli_config, &mut reader, &mut rng)?;
+            if cli.ensure_change() {
+                let mut attempts = 1;
+                while layout.is_passthrough() && attempts < CHANGE_ATTEMPTS {
+                    layout = build_layout(&cli, cli_config, &mut reader, &mut rng)?;
+                    attempts += 1;
+                }
+                if layout.is_passthrough() {
+                    return Err(anyhow!(
+                        "layout left the input unchanged in {attempts} attempts"
+                    ));
+                }
+            }
+            if let Some(path) = cache {
+                layout.save(path)?;
+            }
+            layout
+        }
+    };
+
+    let intermediate = cli.intermediate().map(|path| (path, layout.passthrough()));
+
+    if let Some(path) = cli.layout_svg() {
+        layout_svg::write(&layout, path)?;
+    }
+
+    if cli.coverage() {
+        let coverage: Vec<String> = layout
+            .coverage()
+            .into_iter()
+            .map(|(effect, fraction)| format!("{:.0}% {effect}", 100. * fraction))
+            .collect();
+        print_report(&cli, format!("Coverage: {}", coverage.join(", ")));
+    }
+
+    if cli.benchmark() {
+        let mut writer = NullWriter::default();
+        let start = Instant::now();
+        process(&mut reader, &mut writer, layout, cli_config, &mut stages)?;
+        let elapsed = start.elapsed().as_secs_f64();
+        println!(
+            "\nProcessed {} samples in {elapsed:.3} s ({:.0} samples/s)",
+            writer.samples(),
+            writer.samples() as f64 / elapsed
+        );
+        if let Some(checksum) = stages.meters.checksum {
+            println!("Checksum: {:x}", checksum.finalize());
+        }
+        return Ok(());
+    }
+
+    if cli.interactive() && !cli.force() && cli.output().exists() && Condition::stdin_is_tty() {
+        let prompt = format!("Overwrite `{}`?", cli.output().display());
+        if !confirm(&prompt, io::stdin().lock(), io::stderr())? {
+            return Err(anyhow!("output file already exists"));
+        }
+        fs::remove_file(cli.output()).context("when removing output file")?;
+    }
+
+    let create = |spec| -> Result<WavWriter> {
+        if cli.append() && cli.output().exists() {
+            return Ok(WavWriter::append(cli.output(), spec)?.with_limit(cli.max_output_size()));
+        }
+        Ok(WavWriter::create(cli.output(), spec, cli.force())?
+            .with_info(cli.meta())
+            .with_loop(cli.loop_points())
+            .with_limit(cli.max_output_size()))
+    };
+    let writer = if cli.buffered_output() {
+        if cli.output().exists() && !cli.append() && !cli.force() {
+            return Err(anyhow!("output file already exists"));
+        }
+        // Meter the samples as converted and written, after the post pass below
+        stages.meters = Meters::default();
+        let mut buffer = BufferWriter::default();
+        match parallel_config {
+            Some(parallel_config) => {
+                let parallel_layout = build_layout(&cli, parallel_config, &mut reader, &mut rng)?;
+                let chains = vec![(layout, cli_config), (parallel_layout, parallel_config)];
+                process_parallel(&mut reader, &mut buffer, chains, cli_config, &mut stages)?;
+            }
+            None => process(&mut reader, &mut buffer, layout, cli_config, &mut stages)?,
+        }
+        if cli.trim_output_silence() {
+            buffer.trim_silence(reader.spec().channels as usize);
+        }
+        if let Some(target) = cli.lufs() {
+            buffer.normalize_loudness(target, reader.spec(), cli.peak_mode());
+        }
+        let spec = if cli.auto_bit_depth() {
+            buffer.lossless_spec(reader.spec())
+        } else if cli.float() {
+            hound::WavSpec {
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+                ..reader.spec()
+            }
+        } else {
+            reader.spec()
+        };
+        let mut writer = MeteredWriter {
+            writer: create(spec)?,
+            meters: Meters::new(&cli, spec),
+        };
+        buffer.write_into(&mut writer, reader.spec(), spec, cli_config.int_math)?;
+        stages.meters = writer.meters;
+        writer.writer
+    } else {
+        let mut writer = create(reader.spec())?;
+        process(&mut reader, &mut writer, layout, cli_config, &mut stages)?;
+        writer
+    };
+
+    if !cli_config.quiet {
+        println!("\nDone");
+    }
+
+    let frames = writer.duration();
+    writer.finalize()?;
+
+    if let Some(checksum) = stages.meters.checksum {
+        print_report(&cli, format!("Checksum: {:x}", checksum.finalize()));
+    }
+
+    if cli.base64() {
+        let bytes = fs::read(cli.output()).context("when reading output file")?;
+        if bytes.len() > BASE64_WARNING {
+            eprintln!(
+                "{} output is {:.1} MB, too large to share comfortably as base64",
+                "Warning:".bold().bright().yellow(),
+                bytes.len() as f64 / 1e6
+            );
+        }
+        println!("{}", BASE64_STANDARD.encode(bytes));
+    }
+
+    if let Some(dc) = stages.meters.dc {
+        let offsets = dc.offsets();
+        let report: Vec<String> = offsets.iter().map(|dc| format!("{dc:.4}")).collect();
+        print_report(&cli, format!("DC offset: {}", report.join(", ")));
+        if offsets.iter().any(|dc| dc.abs() > DcMeter::WARNING) {
+            eprintln!(
+                "{} output has a DC offset above -40 dBFS",
+                "Warning:".bold().bright().yellow()
+            );
+        }
+    }
+
+    if let (Some(path), Some(peaks)) = (cli.peaks(), stages.meters.peaks) {
+        peaks.write(path)?;
+    }
+
+    if let (Some(path), Some(analysis)) = (cli.analysis(), stages.analysis) {
+        analysis.write(path)?;
+    }
+
+    if let Some((path, layout)) = intermediate {
+        let mut writer = WavWriter::create(path, reader.spec(), cli.force())?;
+        let cli_config = CliConfig {
+            quiet: true,
+            ..Default::default()
+        };
+        process(
+            &mut reader,
+            &mut writer,
+            layout,
+            cli_config,
+            &mut Stages::default(),
+        )?;
+        writer.finalize()?;
+    }
+
+    if let Some(path) = cli.log() {
+        RunLog {
+            timestamp: cli.start_time(),
+            input: cli.input(),
+            output: cli.output(),
+            config: cli_config,
+            seed,
+            warnings,
+            frames,
+            elapsed: started.elapsed().as_secs_f64(),
+        }
+        .append(path)?;
+    }
+
+    Ok(())
+}
+
+/// Renders the first `preview` seconds of each of `seeds` as `-S` would, one after another
+/// with a silence between them
+fn sweep(
+    cli: &Cli,
+    cli_config: CliConfig,
+    mut reader: WavReader,
+    seeds: RangeInclusive<u64>,
+    preview: f64,
+) -> Result<()> {
+    let spec = reader.spec();
+    let samples_len =
+        |secs: f64| (secs * spec.sample_rate as f64) as usize * spec.channels as usize;
+    let mut writer = WavWriter::create(cli.output(), spec, cli.force())?;
+    let quiet = cli_config.quiet;
+    let cli_config = CliConfig {
+        quiet: true,
+        ..cli_config
+    };
+
+    for seed in seeds.clone() {
+        if !quiet {
+            let start = writer.duration() as f64 / spec.sample_rate as f64;
+            eprintln!("Seed {} at {start:.1} s", cli.seed_format().format(seed));
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        reader = reader.with_seed(rng.gen());
+        let layout = build_layout(cli, cli_config, &mut reader, &mut rng)?;
+        let mut buffer = BufferWriter::default();
+        let mut stages = Stages::new(cli, spec)?;
+        process(&mut reader, &mut buffer, layout, cli_config, &mut stages)?;
+        let gap = if seed == *seeds.end() { 0. } else { SWEEP_GAP };
+        buffer.resize(samples_len(preview));
+        buffer.resize(samples_len(preview) + samples_len(gap));
+        buffer.write_into(&mut writer, spec, spec, cli_config.int_math)?;
+    }
+    writer.finalize()
+}
+
+/// Prints a report line, to stderr when stdout is taken by the base64 output
+fn print_report(cli: &Cli, line: String) {
+    if cli.base64() {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+/// Checks that `cli_config` can be applied to the input of `reader`
+fn check(cli_config: CliConfig, reader: &WavReader) -> Result<()> {
+    if cli_config.start * reader.config().sample_rate as f64 >= reader.config().duration as f64 {
+        return Err(anyhow!("start position is beyond the end of input"));
+    }
+
+    if cli_config.rotate_channels >= reader.spec().channels {
+        return Err(anyhow!(
+            "channel rotation {} is not in 0..{}",
+            cli_config.rotate_channels,
+            reader.spec().channels
+        ));
+    }
+
+    if cli_config.decorrelate > 0. && reader.spec().channels != 2 {
+        return Err(anyhow!("decorrelation requires a stereo input"));
+    }
+
+    if cli_config.mid_glitch && reader.spec().channels != 2 {
+        return Err(anyhow!("mid glitching requires a stereo input"));
+    }
+
+    Ok(())
+}
+
+fn build_layout(
+    cli: &Cli,
+    cli_config: CliConfig,
+    reader: &mut WavReader,
+    rng: &mut StdRng,
+) -> Result<SegmentLayout> {
+    let curve = match (cli.prob_curve_file(), cli.sidechain()) {
+        (Some(path), _) => SegmentLayout::read_curve(path)?,
+        (None, Some(path)) => {
+            let mut sidechain = WavReader::open(path)?;
+            if sidechain.config().sample_rate != reader.config().sample_rate {
+                return Err(anyhow!(
+                    "sidechain sample rate {} does not match input sample rate {}",
+                    sidechain.config().sample_rate,
+                    reader.config().sample_rate
+                ));
+            }
+            if sidechain.config().duration < reader.config().duration {
+                return Err(anyhow!("sidechain is shorter than the input"));
+            }
+            SegmentLayout::sidechain_curve(cli_config, reader.config(), &sidechain.read_all()?)
+        }
+        (None, None) => vec![],
+    };
+    let channel_configs = match cli.prob_matrix() {
+        Some(path) => SegmentLayout::read_prob_matrix(path, cli_config, reader.spec().channels)?,
+        None => vec![],
+    };
+    Ok(if cli.transient_segments() {
+        let onsets = onsets::detect(&reader.read_all()?, reader.spec().sample_rate);
+        SegmentLayout::build_transient(
+            cli_config,
+            reader.config(),
+            &onsets,
+            &curve,
+            &channel_configs,
+            rng,
+        )
+    } else {
+        SegmentLayout::build(cli_config, reader.config(), &curve, &channel_configs, rng)
+    })
+}
+
+/// Asks a yes/no question, taking anything but yes as no
+fn confirm<R: BufRead, W: Write>(prompt: &str, mut input: R, mut output: W) -> Result<bool> {
+    write!(output, "{prompt} [y/N] ")?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
 
-    println!("{}", cli.defaults());
+#[derive(Default)]
+struct Stages {
+    decorrelator: Option<Decorrelator>,
+    delay: Option<ChannelDelay>,
+    #[cfg(feature = "fft")]
+    reverb: Option<Reverb>,
+    compressor: Option<Compressor>,
+    analysis: Option<Analysis>,
+    meters: Meters,
+    silence: f64,
+}
+
+/// Measurements of the samples written to the output
+#[derive(Default)]
+struct Meters {
+    peaks: Option<Peaks>,
+    checksum: Option<Sha256>,
+    dc: Option<DcMeter>,
+}
+
+/// Writer measuring the samples it passes on to `writer`
+struct MeteredWriter<W> {
+    writer: W,
+    meters: Meters,
+}
 
-    let layout = SegmentLayout::build(cli.config(), reader.config());
+impl Stages {
+    const TAIL_BLOCK_LEN: usize = 1024;
+    /// Maximal length in seconds of a rendered tail
+    const MAX_TAIL: usize = 30;
+    /// Maximal delay in seconds of a channel
+    const MAX_DELAY: u32 = 10;
 
+    fn new(cli: &Cli, spec: hound::WavSpec) -> Result<Stages> {
+        let cli_config = cli.config();
+        let decorrelator = (cli_config.decorrelate > 0.).then(|| {
+            Decorrelator::new(
+                cli_config.decorrelate,
+                spec.sample_rate,
+                spec.bits_per_sample,
+                cli_config.int_math,
+            )
+        });
+        let delays = cli.channel_delays();
+        if !delays.is_empty() && delays.len() != spec.channels as usize {
+            return Err(anyhow!(
+                "channel delays list has {} values for {} channels",
+                delays.len(),
+                spec.channels
+            ));
+        }
+        let max_delay = Self::MAX_DELAY * spec.sample_rate;
+        if let Some(&delay) = delays.iter().find(|&&delay| delay > max_delay) {
+            return Err(anyhow!(
+                "channel delay of {delay} samples is longer than {} s",
+                Self::MAX_DELAY
+            ));
+        }
+        let delay = (!delays.is_empty()).then(|| ChannelDelay::new(&delays));
+        #[cfg(feature = "fft")]
+        let reverb = match cli.reverb() {
+            Some(path) => {
+                let mut impulse = WavReader::open(path)?;
+                if impulse.spec().sample_rate != spec.sample_rate {
+                    return Err(anyhow!(
+                        "impulse response sample rate {} does not match input sample rate {}",
+                        impulse.spec().sample_rate,
+                        spec.sample_rate
+                    ));
+                }
+                Some(Reverb::new(
+                    impulse.read_all()?,
+                    spec.channels as usize,
+                    spec.bits_per_sample,
+                    cli_config.int_math,
+                )?)
+            }
+            None => None,
+        };
+        let compressor = (cli_config.glue_ratio > 1.).then(|| {
+            Compressor::new(
+                cli_config.glue_ratio,
+                cli_config.glue_threshold,
+                spec,
+                cli_config.int_math,
+            )
+        });
+        let analysis = cli.analysis().map(|_| Analysis::new(spec, cli.peak_mode()));
+        // -90 dBFS
+        let silence = match spec.sample_format {
+            hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f64,
+            hound::SampleFormat::Float => 1.,
+        } * 10f64.powf(-4.5);
+
+        Ok(Stages {
+            decorrelator,
+            delay,
+            #[cfg(feature = "fft")]
+            reverb,
+            compressor,
+            analysis,
+            meters: Meters::new(cli, spec),
+            silence,
+        })
+    }
+
+    fn process<S: SampleMath>(&mut self, mut samples: Vec<S>) -> Vec<S> {
+        if let Some(decorrelator) = &mut self.decorrelator {
+            decorrelator.process(&mut samples);
+        }
+        if let Some(delay) = &mut self.delay {
+            delay.process(&mut samples);
+        }
+        #[cfg(feature = "fft")]
+        if let Some(reverb) = &mut self.reverb {
+            samples = reverb.process(&samples);
+        }
+        if let Some(compressor) = &mut self.compressor {
+            compressor.process(&mut samples);
+        }
+        self.meters.push(&samples);
+        samples
+    }
+
+    fn flush<S: SampleMath>(&mut self) -> Vec<S> {
+        let mut samples = match &mut self.delay {
+            Some(delay) => delay.flush(),
+            None => vec![],
+        };
+        #[cfg(feature = "fft")]
+        if let Some(reverb) = &mut self.reverb {
+            samples = reverb.process(&samples);
+            samples.extend(reverb.flush::<S>());
+        }
+        if let Some(compressor) = &mut self.compressor {
+            compressor.process(&mut samples);
+        }
+        self.meters.push(&samples);
+        samples
+    }
+
+    fn is_silent<S: SampleMath>(&self, samples: &[S]) -> bool {
+        samples
+            .iter()
+            .all(|sample| sample.to_f64().abs() <= self.silence)
+    }
+}
+
+impl Meters {
+    fn new(cli: &Cli, spec: hound::WavSpec) -> Meters {
+        Meters {
+            peaks: cli.peaks().map(|_| Peaks::new(spec)),
+            checksum: cli.checksum().then(<Sha256 as Digest>::new),
+            dc: cli.dc_report().then(|| DcMeter::new(spec)),
+        }
+    }
+
+    fn push<S: SampleMath>(&mut self, samples: &[S]) {
+        if let Some(peaks) = &mut self.peaks {
+            peaks.push(samples);
+        }
+        if let Some(checksum) = &mut self.checksum {
+            for sample in samples {
+                checksum.update(sample.to_f64().to_le_bytes());
+            }
+        }
+        if let Some(dc) = &mut self.dc {
+            dc.push(samples);
+        }
+    }
+}
+
+impl<W: SampleWriter> SampleWriter for MeteredWriter<W> {
+    fn write<S: SampleMath>(&mut self, samples: &[S]) -> Result<()> {
+        self.meters.push(samples);
+        self.writer.write(samples)
+    }
+
+    fn finalize(self) -> Result<()> {
+        self.writer.finalize()
+    }
+}
+
+fn process<W: SampleWriter>(
+    reader: &mut WavReader,
+    writer: &mut W,
+    layout: SegmentLayout,
+    cli_config: CliConfig,
+    stages: &mut Stages,
+) -> Result<()> {
+    match reader.spec().sample_format {
+        hound::SampleFormat::Int => {
+            process_samples::<i32, W>(reader, writer, layout, cli_config, stages)
+        }
+        hound::SampleFormat::Float => {
+            process_samples::<f32, W>(reader, writer, layout, cli_config, stages)
+        }
+    }
+}
+
+fn process_samples<S: SampleMath, W: SampleWriter>(
+    reader: &mut WavReader,
+    writer: &mut W,
+    layout: SegmentLayout,
+    cli_config: CliConfig,
+    stages: &mut Stages,
+) -> Result<()> {
     let mut threshold = 0;
+    let mut percentage = 0.;
+    let channels = reader.spec().channels as usize;
+    let crossfade_len = (cli_config.crossfade * reader.spec().sample_rate as f64).round() as usize;
+    // Continuation of the previous slice, none before the first one
+    let mut tail: Vec<S> = vec![];
 
     for mut slice in layout {
-        match reader.spec().sample_format {
-            hound::SampleFormat::Int => writer.write(&reader.read::<i32>(&mut slice)?),
-            hound::SampleFormat::Float => writer.write(&reader.read::<f32>(&mut slice)?),
-        }?;
+        debug_assert!(
+            (percentage..=100.).contains(&slice.percentage()),
+            "progress went from {percentage}% to {}%",
+            slice.percentage()
+        );
+        percentage = slice.percentage();
+        let mut samples = reader.read::<S>(&mut slice, cli_config)?;
+        if crossfade_len > 0 {
+            let bits = reader.spec().bits_per_sample;
+            effects::crossfade(&mut samples, &tail, channels, bits, cli_config.int_math);
+            tail = reader.read_tail(&slice, cli_config, crossfade_len)?;
+        }
+        if let Some(analysis) = &mut stages.analysis {
+            analysis.push(&slice, &samples);
+        }
+        let samples = stages.process(samples);
+        writer.write(&samples)?;
         if slice.percentage() as u8 > threshold {
-            if Condition::stdout_is_tty() {
+            if !cli_config.quiet && Condition::stdout_is_tty() {
                 print!("\rProcessing... {:.2}%", slice.percentage());
                 io::stdout().flush()?;
             }
             threshold = slice.percentage() as u8;
         }
     }
-    println!("\nDone");
+    finish::<S, W>(writer, reader.spec(), cli_config, stages)
+}
+
+/// Renders each chain without output stages, then passes their mix at equal gain through
+/// the stages
+fn process_parallel<W: SampleWriter>(
+    reader: &mut WavReader,
+    writer: &mut W,
+    chains: Vec<(SegmentLayout, CliConfig)>,
+    cli_config: CliConfig,
+    stages: &mut Stages,
+) -> Result<()> {
+    match reader.spec().sample_format {
+        hound::SampleFormat::Int => {
+            process_parallel_samples::<i32, W>(reader, writer, chains, cli_config, stages)
+        }
+        hound::SampleFormat::Float => {
+            process_parallel_samples::<f32, W>(reader, writer, chains, cli_config, stages)
+        }
+    }
+}
 
-    writer.finalize()?;
+fn process_parallel_samples<S: SampleMath, W: SampleWriter>(
+    reader: &mut WavReader,
+    writer: &mut W,
+    chains: Vec<(SegmentLayout, CliConfig)>,
+    cli_config: CliConfig,
+    stages: &mut Stages,
+) -> Result<()> {
+    let gain = 1. / chains.len() as f64;
+    let mut mix = BufferWriter::default();
+    for (layout, chain_config) in chains {
+        let chain_config = CliConfig {
+            render_tail: false,
+            ..chain_config
+        };
+        let mut chain = BufferWriter::default();
+        process_samples::<S, _>(
+            reader,
+            &mut chain,
+            layout,
+            chain_config,
+            &mut Stages::default(),
+        )?;
+        mix.mix(&chain, gain);
+    }
+
+    let bits = reader.spec().bits_per_sample;
+    let block_len = Stages::TAIL_BLOCK_LEN * reader.spec().channels as usize;
+    for block in mix.samples().chunks(block_len) {
+        let block: Vec<S> = block
+            .iter()
+            .map(|&sample| S::from_f64_clamped(sample, bits, cli_config.int_math))
+            .collect();
+        writer.write(&stages.process(block))?;
+    }
+
+    finish::<S, W>(writer, reader.spec(), cli_config, stages)
+}
+
+/// Writes the tail rendered by the stages, if asked for, and what remains in them
+fn finish<S: SampleMath, W: SampleWriter>(
+    writer: &mut W,
+    spec: hound::WavSpec,
+    cli_config: CliConfig,
+    stages: &mut Stages,
+) -> Result<()> {
+    if cli_config.render_tail {
+        let channels = spec.channels as usize;
+        let max_frames = Stages::MAX_TAIL * spec.sample_rate as usize;
+        let mut frames = 0;
+        while frames < max_frames {
+            let samples = stages.process(vec![S::from(0); Stages::TAIL_BLOCK_LEN * channels]);
+            writer.write(&samples)?;
+            if stages.is_silent(&samples) {
+                break;
+            }
+            frames += Stages::TAIL_BLOCK_LEN;
+        }
+    }
+    writer.write(&stages.flush::<S>())?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirm_yes() {
+        let mut output = vec![];
+        assert!(confirm("Overwrite?", "yes\n".as_bytes(), &mut output).unwrap());
+        assert!(confirm("Overwrite?", "Y\n".as_bytes(), &mut output).unwrap());
+        assert_eq!(output, b"Overwrite? [y/N] Overwrite? [y/N] ");
+    }
+
+    #[test]
+    fn confirm_no() {
+        assert!(!confirm("Overwrite?", "n\n".as_bytes(), vec![]).unwrap());
+        assert!(!confirm("Overwrite?", "\n".as_bytes(), vec![]).unwrap());
+        assert!(!confirm("Overwrite?", "".as_bytes(), vec![]).unwrap());
+    }
+}
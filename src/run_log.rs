@@ -0,0 +1,36 @@
+use crate::cli::CliConfig;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Record of a run, appended to the log as a line of JSON
+#[derive(Serialize)]
+pub struct RunLog {
+    pub timestamp: String,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub config: CliConfig,
+    /// Seed of the random generator, given or drawn for the run
+    pub seed: u64,
+    pub warnings: Vec<String>,
+    /// Frames written to the output
+    pub frames: u32,
+    /// Seconds taken by the run
+    pub elapsed: f64,
+}
+
+impl RunLog {
+    pub fn append<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut line = serde_json::to_vec(self).context("when writing log")?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("when opening log")?
+            .write_all(&line)
+            .context("when writing log")
+    }
+}
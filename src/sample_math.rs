@@ -0,0 +1,98 @@
+use crate::cli::IntMath;
+
+/// Rounds `value` into a signed integer of `bits`, handling overflow as `mode` says
+fn fit(mode: IntMath, value: f64, bits: u16) -> i32 {
+    let max = (1i64 << (bits - 1)) - 1;
+    let min = -max - 1;
+    let value = value.round();
+    if (min as f64..=max as f64).contains(&value) {
+        return value as i32;
+    }
+    match mode {
+        IntMath::Saturate => value.clamp(min as f64, max as f64) as i32,
+        IntMath::Wrap => ((value as i64 - min).rem_euclid(max - min + 1) + min) as i32,
+        IntMath::Panic => panic!("sample value {value} overflows {bits} bits"),
+    }
+}
+
+pub trait SampleMath: hound::Sample + Copy + From<i16> {
+    fn to_f64(self) -> f64;
+    fn from_f64(value: f64) -> Self;
+    /// Converts `value` into a sample of `bits`, handling overflow as `mode` says
+    fn from_f64_clamped(value: f64, bits: u16, mode: IntMath) -> Self;
+}
+
+impl SampleMath for i32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round() as i32
+    }
+
+    fn from_f64_clamped(value: f64, bits: u16, mode: IntMath) -> Self {
+        fit(mode, value, bits)
+    }
+}
+
+impl SampleMath for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn from_f64_clamped(value: f64, _bits: u16, _mode: IntMath) -> Self {
+        value as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_from_f64_rounds() {
+        assert_eq!(i32::from_f64(1.5), 2);
+        assert_eq!(i32::from_f64(-1.4), -1);
+    }
+
+    #[test]
+    fn int_from_f64_clamped() {
+        assert_eq!(i32::from_f64_clamped(40000., 16, IntMath::Saturate), 32767);
+        assert_eq!(
+            i32::from_f64_clamped(-40000., 16, IntMath::Saturate),
+            -32768
+        );
+        assert_eq!(i32::from_f64_clamped(100., 8, IntMath::Saturate), 100);
+        assert_eq!(i32::from_f64_clamped(40000., 16, IntMath::Wrap), -25536);
+    }
+
+    #[test]
+    fn fit_saturate() {
+        assert_eq!(fit(IntMath::Saturate, 40000., 16), 32767);
+        assert_eq!(fit(IntMath::Saturate, -40000., 16), -32768);
+    }
+
+    #[test]
+    fn fit_wrap() {
+        assert_eq!(fit(IntMath::Wrap, 40000., 16), -25536);
+        assert_eq!(fit(IntMath::Wrap, -32769., 16), 32767);
+        assert_eq!(fit(IntMath::Wrap, 32767., 16), 32767);
+    }
+
+    #[test]
+    #[should_panic(expected = "sample value 40000 overflows 16 bits")]
+    fn fit_panic() {
+        fit(IntMath::Panic, 40000., 16);
+    }
+
+    #[test]
+    fn int_from_f64_saturates() {
+        assert_eq!(i32::from_f64(1e12), i32::MAX);
+        assert_eq!(i32::from_f64(-1e12), i32::MIN);
+    }
+}
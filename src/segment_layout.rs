@@ -1,95 +1,715 @@
-use crate::cli::CliConfig;
+use crate::cli::{CliConfig, Effect};
 use crate::wav_reader::WavConfig;
-use rand::{thread_rng, Rng};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
 
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct SegmentLayout {
     segments: Vec<Vec<Segment>>,
+    #[serde(skip)]
     index: usize,
-    segment_len: usize,
+    slots: Vec<(u32, usize)>,
 }
 
 impl SegmentLayout {
-    pub fn build(cli_config: CliConfig, wav_config: WavConfig) -> SegmentLayout {
+    /// Builds a layout on a fixed grid, with effect probabilities scaled by `curve`
+    /// (see `curve_at`) and taken for each channel from `channel_configs` if any, drawing
+    /// from `rng` so that a seed reproduces it
+    pub fn build(
+        cli_config: CliConfig,
+        wav_config: WavConfig,
+        curve: &[f64],
+        channel_configs: &[CliConfig],
+        rng: &mut StdRng,
+    ) -> SegmentLayout {
+        let segment_len = Self::segment_len(
+            wav_config.sample_rate,
+            cli_config.tempo,
+            cli_config.segment_length,
+        );
+        let start = Self::seconds_to_samples(cli_config.start, wav_config.sample_rate);
+        let lead_len = Self::lead_len(cli_config.grid_offset, segment_len);
+        let (segment_count, _) = Self::segment_count(
+            wav_config.duration.saturating_sub(start + lead_len),
+            segment_len,
+        );
+        let mut slots = Vec::with_capacity(segment_count + 1);
+        if lead_len > 0 {
+            slots.push((start, lead_len as usize));
+        }
+        slots.extend(
+            (0..segment_count as u32)
+                .map(|i| (start + lead_len + i * segment_len, segment_len as usize)),
+        );
+
+        Self::assemble(
+            cli_config,
+            wav_config,
+            slots,
+            channel_configs,
+            rng,
+            |cli_config, rng| Self::build_channel(cli_config, wav_config, curve, rng),
+        )
+    }
+
+    /// Builds a layout whose segments start at `onsets` instead of a fixed grid
+    pub fn build_transient(
+        cli_config: CliConfig,
+        wav_config: WavConfig,
+        onsets: &[u32],
+        curve: &[f64],
+        channel_configs: &[CliConfig],
+        rng: &mut StdRng,
+    ) -> SegmentLayout {
+        let start = Self::seconds_to_samples(cli_config.start, wav_config.sample_rate);
+        let mut offsets = vec![start];
+        offsets.extend(
+            onsets
+                .iter()
+                .filter(|&&onset| onset > start && onset < wav_config.duration),
+        );
+        let slots: Vec<_> = offsets
+            .iter()
+            .zip(offsets.iter().skip(1).chain([&wav_config.duration]))
+            .map(|(&offset, &end)| (offset, (end - offset) as usize))
+            .collect();
+        let tail = Self::seconds_to_samples(cli_config.clean_tail, wav_config.sample_rate);
+        let glitch_count = slots
+            .iter()
+            .take_while(|&&(offset, len)| {
+                tail == 0 || offset + len as u32 <= wav_config.duration.saturating_sub(tail)
+            })
+            .count();
+
+        Self::assemble(
+            cli_config,
+            wav_config,
+            slots,
+            channel_configs,
+            rng,
+            |cli_config, rng| {
+                Self::glitch_channel(
+                    cli_config,
+                    wav_config,
+                    &offsets,
+                    glitch_count,
+                    glitch_count,
+                    curve,
+                    rng,
+                )
+            },
+        )
+    }
+
+    /// Loads a layout saved with `save`, checking that it fits the input
+    pub fn from_file<P: AsRef<Path>>(path: P, wav_config: WavConfig) -> Result<SegmentLayout> {
+        let file = File::open(path).context("when opening layout cache")?;
+        let layout: SegmentLayout =
+            serde_json::from_reader(BufReader::new(file)).context("when reading layout cache")?;
+
+        let valid = layout.segments.len() == wav_config.channels as usize
+            && layout
+                .slots
+                .iter()
+                .all(|&(offset, _)| offset < wav_config.duration)
+            && layout.segments.iter().all(|channel| {
+                channel.len() == layout.slots.len()
+                    && channel
+                        .iter()
+                        .all(|segment| segment.offset < wav_config.duration)
+            });
+        if !valid {
+            return Err(anyhow!("cached layout does not match input"));
+        }
+
+        Ok(layout)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = File::create(path).context("when creating layout cache")?;
+        serde_json::to_writer(BufWriter::new(file), self).context("when writing layout cache")
+    }
+
+    /// Segments of each channel, one per slot
+    pub fn channels(&self) -> &[Vec<Segment>] {
+        &self.segments
+    }
+
+    /// Offset and length of each slot of the timeline
+    pub fn slots(&self) -> &[(u32, usize)] {
+        &self.slots
+    }
+
+    /// Layout with the same slots whose segments play their own slot without effects
+    pub fn passthrough(&self) -> SegmentLayout {
+        let channel: Vec<Segment> = self
+            .slots
+            .iter()
+            .map(|&(offset, _)| Segment::new(offset, false, false))
+            .collect();
+
+        SegmentLayout {
+            segments: vec![channel; self.segments.len()],
+            index: 0,
+            slots: self.slots.clone(),
+        }
+    }
+
+    /// Whether the layout plays every segment in order without effects
+    pub fn is_passthrough(&self) -> bool {
+        self.segments == self.passthrough().segments
+    }
+
+    /// Fraction of the timeline each effect covers, weighting segments by their length and
+    /// averaging over channels
+    pub fn coverage(&self) -> Vec<(&'static str, f64)> {
+        let names = [
+            "moved",
+            "reversed",
+            "silenced",
+            "frozen",
+            "granular",
+            "beat repeated",
+            "widened",
+            "gained",
+        ];
+        let mut covered = [0usize; 8];
+        for channel in &self.segments {
+            for (segment, &(offset, len)) in channel.iter().zip(&self.slots) {
+                let flags = [
+                    segment.offset != offset,
+                    segment.reverse,
+                    segment.silence,
+                    segment.freeze,
+                    segment.granular,
+                    segment.beat_repeat,
+                    segment.invert,
+                    segment.gain.is_some(),
+                ];
+                for (covered, _) in covered.iter_mut().zip(flags).filter(|&(_, flag)| flag) {
+                    *covered += len;
+                }
+            }
+        }
+        let total = self.slots.iter().map(|&(_, len)| len).sum::<usize>() * self.segments.len();
+
+        names
+            .into_iter()
+            .zip(covered)
+            .map(|(name, covered)| (name, covered as f64 / total.max(1) as f64))
+            .collect()
+    }
+
+    /// Fraction of segments, across channels, that differ from those of `other` in the
+    /// same slot
+    pub fn difference(&self, other: &SegmentLayout) -> f64 {
+        let total = self.segments.iter().map(Vec::len).sum::<usize>();
+        let differing = self
+            .segments
+            .iter()
+            .zip(&other.segments)
+            .flat_map(|(channel, other)| channel.iter().zip(other))
+            .filter(|(segment, other)| segment != other)
+            .count();
+        differing as f64 / total.max(1) as f64
+    }
+
+    /// Average difference between every pair of `layouts`
+    pub fn variance(layouts: &[SegmentLayout]) -> f64 {
+        let differences: Vec<f64> = layouts
+            .iter()
+            .enumerate()
+            .flat_map(|(i, layout)| {
+                layouts[i + 1..]
+                    .iter()
+                    .map(|other| layout.difference(other))
+            })
+            .collect();
+        differences.iter().sum::<f64>() / differences.len().max(1) as f64
+    }
+
+    fn assemble<F: Fn(CliConfig, &mut StdRng) -> Vec<Segment>>(
+        cli_config: CliConfig,
+        wav_config: WavConfig,
+        slots: Vec<(u32, usize)>,
+        channel_configs: &[CliConfig],
+        rng: &mut StdRng,
+        build_channel: F,
+    ) -> SegmentLayout {
         let mut segments = vec![vec![]; wav_config.channels as usize];
 
-        if cli_config.each_channel_separately {
+        if !channel_configs.is_empty() {
+            for (channel, &channel_config) in segments.iter_mut().zip(channel_configs) {
+                *channel = build_channel(channel_config, rng);
+            }
+        } else if cli_config.each_channel_separately {
+            // Channels draw one after another from the same stream, so each gets its own
+            // sequence while the seed still reproduces all of them
             for channel in &mut segments {
-                *channel = Self::build_channel(cli_config, wav_config);
+                *channel = build_channel(cli_config, rng);
             }
         } else {
-            let channel = Self::build_channel(cli_config, wav_config);
+            let channel = build_channel(cli_config, rng);
             segments.fill(channel);
         }
 
-        let segment_len = Self::segment_len(
-            wav_config.sample_rate,
-            cli_config.tempo,
-            cli_config.segment_length,
-        ) as usize;
+        // Only the second channel is inverted, so that widening opens up the stereo image
+        if let [left, _] = segments.as_mut_slice() {
+            for segment in left {
+                *segment = segment.with_invert(false);
+            }
+        }
 
         SegmentLayout {
             segments,
             index: 0,
-            segment_len,
+            slots,
         }
     }
 
-    fn build_channel(cli_config: CliConfig, wav_config: WavConfig) -> Vec<Segment> {
+    fn build_channel(
+        cli_config: CliConfig,
+        wav_config: WavConfig,
+        curve: &[f64],
+        rng: &mut StdRng,
+    ) -> Vec<Segment> {
         let segment_len = Self::segment_len(
             wav_config.sample_rate,
             cli_config.tempo,
             cli_config.segment_length,
         );
-        let (segment_count, is_incomplete) = Self::segment_count(wav_config.duration, segment_len);
-        let mut channel = Vec::with_capacity(segment_count);
-        let mut rng = thread_rng();
+        let start = Self::seconds_to_samples(cli_config.start, wav_config.sample_rate);
+        let lead_len = Self::lead_len(cli_config.grid_offset, segment_len);
+        let (segment_count, _) = Self::segment_count(
+            wav_config.duration.saturating_sub(start + lead_len),
+            segment_len,
+        );
+        let glitch_count = Self::glitch_count(
+            wav_config.duration.saturating_sub(start + lead_len),
+            segment_len,
+            segment_count,
+            Self::seconds_to_samples(cli_config.clean_tail, wav_config.sample_rate),
+        );
+        let offsets: Vec<_> = (0..segment_count as u32)
+            .map(|i| start + lead_len + i * segment_len)
+            .collect();
+        let mut channel = Self::glitch_channel(
+            cli_config,
+            wav_config,
+            &offsets,
+            glitch_count,
+            Self::movable_count(cli_config, wav_config),
+            curve,
+            rng,
+        );
 
-        for i in 0..segment_count {
-            channel.push(Segment::new(
-                i as u32 * segment_len,
-                rng.gen_bool(cli_config.prob_reverse),
-                rng.gen_bool(cli_config.prob_silence),
-            ));
+        if lead_len > 0 {
+            channel.insert(0, Segment::new(start, false, false));
+        }
+
+        channel
+    }
+
+    fn glitch_channel(
+        cli_config: CliConfig,
+        wav_config: WavConfig,
+        offsets: &[u32],
+        glitch_count: usize,
+        movable_count: usize,
+        curve: &[f64],
+        rng: &mut StdRng,
+    ) -> Vec<Segment> {
+        let mut channel = Vec::with_capacity(offsets.len() + 1);
+        let beats = Self::beats(cli_config, wav_config, offsets);
+        let widen = wav_config.channels == 2;
+        let probability = |effect, i| {
+            if !beats.get(i).copied().unwrap_or(true) {
+                return 0.;
+            }
+            let position = Self::position(i, offsets.len());
+            cli_config.probability(effect, position) * Self::curve_at(curve, position)
+        };
+
+        if let Some(budget) = cli_config.budget {
+            channel.extend(
+                offsets
+                    .iter()
+                    .map(|&offset| Segment::new(offset, false, false)),
+            );
+            Self::spend_budget(
+                cli_config,
+                &mut channel[..glitch_count],
+                budget,
+                movable_count,
+                widen,
+                probability,
+                rng,
+            );
+            Self::space_out(&mut channel, offsets, cli_config.min_gap);
+            return channel;
         }
 
-        for i in 0..segment_count {
-            if rng.gen_bool(cli_config.prob_swap) {
-                let swap = (i + rng.gen_range(1..=cli_config.max_swap as usize)).min(
-                    segment_count
-                        - if is_incomplete && i != segment_count - 1 {
-                            2
-                        } else {
-                            1
-                        },
+        let mut streams = Self::streams(cli_config, rng);
+        let mut chance =
+            |effect: Effect, i| Self::chance(&mut streams[effect as usize], probability(effect, i));
+        for (i, &offset) in offsets.iter().enumerate() {
+            if i < glitch_count {
+                channel.push(
+                    Segment::new(
+                        offset,
+                        chance(Effect::Reverse, i),
+                        chance(Effect::Silence, i),
+                    )
+                    .with_freeze(chance(Effect::Freeze, i))
+                    .with_granular(chance(Effect::Granular, i))
+                    .with_beat_repeat(chance(Effect::BeatRepeat, i))
+                    .with_gain(chance(Effect::Gain, i).then_some(cli_config.gain_amount as f32))
+                    .with_invert(widen && chance(Effect::Widen, i)),
                 );
-                channel.swap(i, swap);
+            } else {
+                channel.push(Segment::new(offset, false, false));
+            }
+        }
+
+        let rng = &mut streams[Effect::Swap as usize];
+        for i in 0..glitch_count {
+            if Self::chance(rng, probability(Effect::Swap, i)) {
+                let swap = i + rng.gen_range(1..=cli_config.max_swap as usize);
+                if i < movable_count {
+                    channel.swap(i, swap.min(movable_count - 1));
+                }
             }
         }
 
+        let rng = &mut streams[Effect::Repeat as usize];
         let mut i = 0;
 
-        while i < segment_count {
-            if rng.gen_bool(cli_config.prob_repeat) {
-                let repeat = (i + rng.gen_range(1..=cli_config.max_repeat as usize)).min(
-                    segment_count
-                        - if is_incomplete && i != segment_count - 1 {
-                            2
-                        } else {
-                            1
-                        },
-                );
-                let tmp = channel[i];
-                channel[i..=repeat].fill(tmp);
+        while i < glitch_count {
+            if Self::chance(rng, probability(Effect::Repeat, i)) {
+                // `repeat` is the last slot that receives a copy, so even
+                // max_repeat = 1 fills the next slot rather than only slot i
+                let repeat = if i < movable_count {
+                    (i + rng.gen_range(1..=cli_config.max_repeat as usize)).min(movable_count - 1)
+                } else {
+                    i
+                };
+                Self::repeat(&mut channel[i..=repeat], cli_config.repeat_pitch);
                 i = repeat + 1;
             } else {
                 i += 1;
             }
         }
 
+        Self::space_out(&mut channel, offsets, cli_config.min_gap);
         channel
     }
 
+    /// Fills `copies` with its first segment, pitching copy k by k times `pitch` semitones
+    fn repeat(copies: &mut [Segment], pitch: f64) {
+        let first = copies[0];
+        for (k, copy) in copies.iter_mut().enumerate() {
+            *copy = first.with_pitch(first.pitch + k as f64 * pitch);
+        }
+    }
+
+    /// Clears the effects of segments with fewer than `min_gap` clean segments since the
+    /// previous glitched one
+    fn space_out(channel: &mut [Segment], offsets: &[u32], min_gap: usize) {
+        let mut last = None;
+        for (i, (segment, &offset)) in channel.iter_mut().zip(offsets).enumerate() {
+            if *segment == Segment::new(offset, false, false) {
+                continue;
+            }
+            if last.is_some_and(|last| i - last <= min_gap) {
+                *segment = Segment::new(offset, false, false);
+            } else {
+                last = Some(i);
+            }
+        }
+    }
+
+    /// Applies exactly one effect, picked with weights given by `probability`, to each of
+    /// `budget` random segments, or to all of them if fewer can take an effect
+    fn spend_budget<F: Fn(Effect, usize) -> f64>(
+        cli_config: CliConfig,
+        channel: &mut [Segment],
+        budget: usize,
+        movable_count: usize,
+        widen: bool,
+        probability: F,
+        rng: &mut StdRng,
+    ) {
+        const EFFECTS: [Effect; 9] = [
+            Effect::Silence,
+            Effect::Swap,
+            Effect::Reverse,
+            Effect::Repeat,
+            Effect::Freeze,
+            Effect::Granular,
+            Effect::BeatRepeat,
+            Effect::Gain,
+            Effect::Widen,
+        ];
+        let weights = |i| {
+            EFFECTS.map(|effect| match effect {
+                Effect::Swap | Effect::Repeat if i >= movable_count => 0.,
+                Effect::Widen if !widen => 0.,
+                _ => probability(effect, i).max(0.),
+            })
+        };
+        let eligible: Vec<usize> = (0..channel.len())
+            .filter(|&i| weights(i).iter().any(|&weight| weight > 0.))
+            .collect();
+        let mut chosen: Vec<usize> = index::sample(rng, eligible.len(), budget.min(eligible.len()))
+            .into_iter()
+            .map(|k| eligible[k])
+            .collect();
+        chosen.sort_unstable();
+
+        for i in chosen {
+            let Ok(distribution) = WeightedIndex::new(weights(i)) else {
+                continue;
+            };
+            let offset = channel[i].offset();
+            match EFFECTS[distribution.sample(rng)] {
+                Effect::Silence => channel[i] = Segment::new(offset, false, true),
+                Effect::Reverse => channel[i] = Segment::new(offset, true, false),
+                Effect::Freeze => channel[i] = channel[i].with_freeze(true),
+                Effect::Granular => channel[i] = channel[i].with_granular(true),
+                Effect::BeatRepeat => channel[i] = channel[i].with_beat_repeat(true),
+                Effect::Gain => {
+                    channel[i] = channel[i].with_gain(Some(cli_config.gain_amount as f32))
+                }
+                Effect::Widen => channel[i] = channel[i].with_invert(true),
+                Effect::Swap if i < movable_count => {
+                    let swap = i + rng.gen_range(1..=cli_config.max_swap as usize);
+                    channel.swap(i, swap.min(movable_count - 1));
+                }
+                Effect::Repeat if i < movable_count => {
+                    let repeat = (i + rng.gen_range(1..=cli_config.max_repeat as usize))
+                        .min(movable_count - 1);
+                    Self::repeat(&mut channel[i..=repeat], cli_config.repeat_pitch);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether each segment starting at `offsets` holds the start of a beat at the tempo,
+    /// beats being counted from the first segment, or nothing unless only beats are glitched
+    fn beats(cli_config: CliConfig, wav_config: WavConfig, offsets: &[u32]) -> Vec<bool> {
+        let (true, Some(&origin)) = (cli_config.on_beats, offsets.first()) else {
+            return vec![];
+        };
+        let beat_len = wav_config.sample_rate as f64 * 60. / cli_config.tempo;
+        let ends = offsets.iter().skip(1).chain([&wav_config.duration]);
+
+        offsets
+            .iter()
+            .zip(ends)
+            .map(|(&offset, &end)| {
+                let phase = (offset - origin) as f64 % beat_len;
+                let next_beat = if phase == 0. { 0. } else { beat_len - phase };
+                (offset as f64 + next_beat) < end as f64
+            })
+            .collect()
+    }
+
+    /// Number of segments that swapping and repeating can move between
+    pub fn movable_count(cli_config: CliConfig, wav_config: WavConfig) -> usize {
+        let segment_len = Self::segment_len(
+            wav_config.sample_rate,
+            cli_config.tempo,
+            cli_config.segment_length,
+        );
+        let start = Self::seconds_to_samples(cli_config.start, wav_config.sample_rate);
+        let duration = wav_config
+            .duration
+            .saturating_sub(start + Self::lead_len(cli_config.grid_offset, segment_len));
+        let (segment_count, is_incomplete) = Self::segment_count(duration, segment_len);
+        let glitch_count = Self::glitch_count(
+            duration,
+            segment_len,
+            segment_count,
+            Self::seconds_to_samples(cli_config.clean_tail, wav_config.sample_rate),
+        );
+
+        if is_incomplete {
+            segment_count - 1
+        } else {
+            segment_count
+        }
+        .min(glitch_count)
+    }
+
+    /// Position of segment `index` out of `count`, 0.0 for the first and 1.0 for the last
+    fn position(index: usize, count: usize) -> f64 {
+        index as f64 / count.saturating_sub(1).max(1) as f64
+    }
+
+    /// Multiplier at `position` of a curve whose points are spread evenly from 0.0 to 1.0,
+    /// interpolated linearly, 1.0 for an empty curve
+    fn curve_at(curve: &[f64], position: f64) -> f64 {
+        let Some(&last) = curve.last() else {
+            return 1.;
+        };
+        let x = position * (curve.len() - 1) as f64;
+        let i = x as usize;
+        match curve.get(i + 1) {
+            Some(next) => curve[i] + (next - curve[i]) * (x - i as f64),
+            None => last,
+        }
+    }
+
+    /// Probability curve with a point per segment of the grid, the RMS level of `sidechain`
+    /// in that segment relative to its loudest segment
+    pub fn sidechain_curve(
+        cli_config: CliConfig,
+        wav_config: WavConfig,
+        sidechain: &[Vec<f64>],
+    ) -> Vec<f64> {
+        let segment_len = Self::segment_len(
+            wav_config.sample_rate,
+            cli_config.tempo,
+            cli_config.segment_length,
+        );
+        let start = Self::seconds_to_samples(cli_config.start, wav_config.sample_rate);
+        let lead_len = Self::lead_len(cli_config.grid_offset, segment_len);
+        let (segment_count, _) = Self::segment_count(
+            wav_config.duration.saturating_sub(start + lead_len),
+            segment_len,
+        );
+        let levels: Vec<f64> = (0..segment_count as u32)
+            .map(|i| {
+                let offset = (start + lead_len + i * segment_len) as usize;
+                let (power, count) = sidechain
+                    .iter()
+                    .flat_map(|channel| channel.iter().skip(offset).take(segment_len as usize))
+                    .fold((0., 0), |(power, count), sample| {
+                        (power + sample * sample, count + 1)
+                    });
+                (power / count.max(1) as f64).sqrt()
+            })
+            .collect();
+        let loudest = levels.iter().copied().fold(0., f64::max);
+        if loudest == 0. {
+            return vec![0.; levels.len()];
+        }
+        levels.into_iter().map(|level| level / loudest).collect()
+    }
+
+    /// Reads a probability curve of multipliers separated by whitespace or commas,
+    /// clamping them to 0.0..=1.0
+    pub fn read_curve<P: AsRef<Path>>(path: P) -> Result<Vec<f64>> {
+        let text = fs::read_to_string(path).context("when reading probability curve")?;
+        let curve = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|value| !value.is_empty())
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .map(|value| value.clamp(0., 1.))
+                    .with_context(|| format!("when reading probability curve value `{value}`"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if curve.is_empty() {
+            return Err(anyhow!("probability curve is empty"));
+        }
+        Ok(curve)
+    }
+
+    /// Reads a probability matrix of a line per channel with a value per effect, in the
+    /// order of `Effect`, into a copy of `cli_config` for each channel
+    pub fn read_prob_matrix<P: AsRef<Path>>(
+        path: P,
+        cli_config: CliConfig,
+        channels: u16,
+    ) -> Result<Vec<CliConfig>> {
+        let text = fs::read_to_string(path).context("when reading probability matrix")?;
+        let effects = Effect::value_variants();
+        let rows: Vec<&str> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        if rows.len() != channels as usize {
+            return Err(anyhow!(
+                "probability matrix has {} rows for {channels} channels",
+                rows.len()
+            ));
+        }
+
+        rows.into_iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let values = line
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|value| !value.is_empty())
+                    .map(|value| {
+                        value
+                            .parse::<f64>()
+                            .map(|value| value.clamp(0., 1.))
+                            .with_context(|| {
+                                format!("when reading probability matrix value `{value}`")
+                            })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if values.len() != effects.len() {
+                    return Err(anyhow!(
+                        "probability matrix row {row} has {} values for {} effects",
+                        values.len(),
+                        effects.len()
+                    ));
+                }
+                Ok(effects
+                    .iter()
+                    .zip(values)
+                    .fold(cli_config, |config, (&effect, probability)| {
+                        config.with_probability(effect, probability)
+                    }))
+            })
+            .collect()
+    }
+
+    /// Random streams of the effects, seeded from `rng` and each effect's salt so that a
+    /// salt redraws the decisions of its effect alone
+    fn streams(cli_config: CliConfig, rng: &mut StdRng) -> [StdRng; 9] {
+        let mut seeds = StdRng::seed_from_u64(rng.gen());
+        std::array::from_fn(|i| {
+            let salt = cli_config.salts.get(Effect::value_variants()[i]);
+            StdRng::seed_from_u64(seeds.gen::<u64>() ^ salt)
+        })
+    }
+
+    fn chance<R: Rng>(rng: &mut R, probability: f64) -> bool {
+        if probability.is_nan() {
+            return false;
+        }
+        rng.gen_bool(probability.clamp(0., 1.))
+    }
+
+    fn seconds_to_samples(seconds: f64, sample_rate: u32) -> u32 {
+        (seconds * sample_rate as f64).round() as u32
+    }
+
+    fn glitch_count(duration: u32, segment_len: u32, segment_count: usize, tail: u32) -> usize {
+        if tail == 0 {
+            return segment_count;
+        }
+        (duration.saturating_sub(tail) / segment_len) as usize
+    }
+
+    fn lead_len(grid_offset: u32, segment_len: u32) -> u32 {
+        grid_offset.checked_rem(segment_len).unwrap_or(0)
+    }
+
     fn segment_len(sample_rate: u32, tempo: f64, note_value: f64) -> u32 {
         (sample_rate as f64 * 240. * note_value / tempo) as u32
     }
@@ -97,7 +717,7 @@ impl SegmentLayout {
     fn segment_count(duration: u32, segment_len: u32) -> (usize, bool) {
         (
             (duration as f64 / segment_len as f64).ceil() as usize,
-            duration % segment_len != 0,
+            !duration.is_multiple_of(segment_len),
         )
     }
 }
@@ -112,13 +732,33 @@ impl Iterator for SegmentLayout {
             vec.push(ch.get(self.index).cloned()?);
         }
 
+        let silent = |ch: &Vec<Segment>, index: Option<usize>| {
+            index
+                .and_then(|index| ch.get(index))
+                .is_some_and(Segment::silence)
+        };
+        let fades = self
+            .segments
+            .iter()
+            .map(|ch| {
+                (
+                    silent(ch, self.index.checked_sub(1)),
+                    silent(ch, Some(self.index + 1)),
+                )
+            })
+            .collect();
+        let (offset, segment_len) = self.slots[self.index];
         self.index += 1;
 
-        Some(SegmentSlice::new(
-            vec,
-            self.segment_len,
-            100. * self.index as f64 / self.segments[0].len() as f64,
-        ))
+        Some(
+            SegmentSlice::new(
+                vec,
+                offset,
+                segment_len,
+                100. * self.index as f64 / self.segments[0].len() as f64,
+            )
+            .with_fades(fades),
+        )
     }
 }
 
@@ -126,20 +766,47 @@ impl Iterator for SegmentLayout {
 pub struct SegmentSlice {
     segments: Vec<Segment>,
     index: usize,
+    offset: u32,
     segment_len: usize,
     percentage: f64,
+    fades: Vec<(bool, bool)>,
 }
 
 impl SegmentSlice {
-    pub fn new(segments: Vec<Segment>, segment_len: usize, percentage: f64) -> SegmentSlice {
+    pub fn new(
+        segments: Vec<Segment>,
+        offset: u32,
+        segment_len: usize,
+        percentage: f64,
+    ) -> SegmentSlice {
         SegmentSlice {
             segments,
             index: 0,
+            offset,
             segment_len,
             percentage,
+            fades: vec![],
         }
     }
 
+    /// Sets whether each channel is preceded and followed by a silenced segment
+    pub fn with_fades(mut self, fades: Vec<(bool, bool)>) -> SegmentSlice {
+        self.fades = fades;
+        self
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn fades(&self) -> &[(bool, bool)] {
+        &self.fades
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
     pub fn segment_len(&self) -> usize {
         self.segment_len
     }
@@ -159,11 +826,19 @@ impl Iterator for SegmentSlice {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Copy)]
+#[derive(Clone, Debug, PartialEq, Copy, Deserialize, Serialize)]
 pub struct Segment {
     offset: u32,
     reverse: bool,
     silence: bool,
+    freeze: bool,
+    granular: bool,
+    beat_repeat: bool,
+    invert: bool,
+    /// Semitones to pitch the segment by
+    pitch: f64,
+    /// Multiplier of the volume of the segment
+    gain: Option<f32>,
 }
 
 impl Segment {
@@ -172,9 +847,45 @@ impl Segment {
             offset,
             reverse,
             silence,
+            freeze: false,
+            granular: false,
+            beat_repeat: false,
+            invert: false,
+            pitch: 0.,
+            gain: None,
         }
     }
 
+    pub fn with_freeze(mut self, freeze: bool) -> Segment {
+        self.freeze = freeze;
+        self
+    }
+
+    pub fn with_granular(mut self, granular: bool) -> Segment {
+        self.granular = granular;
+        self
+    }
+
+    pub fn with_beat_repeat(mut self, beat_repeat: bool) -> Segment {
+        self.beat_repeat = beat_repeat;
+        self
+    }
+
+    pub fn with_invert(mut self, invert: bool) -> Segment {
+        self.invert = invert;
+        self
+    }
+
+    pub fn with_pitch(mut self, pitch: f64) -> Segment {
+        self.pitch = pitch;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: Option<f32>) -> Segment {
+        self.gain = gain;
+        self
+    }
+
     pub fn offset(&self) -> u32 {
         self.offset
     }
@@ -186,16 +897,49 @@ impl Segment {
     pub fn silence(&self) -> bool {
         self.silence
     }
+
+    pub fn freeze(&self) -> bool {
+        self.freeze
+    }
+
+    pub fn granular(&self) -> bool {
+        self.granular
+    }
+
+    pub fn beat_repeat(&self) -> bool {
+        self.beat_repeat
+    }
+
+    pub fn invert(&self) -> bool {
+        self.invert
+    }
+
+    pub fn pitch(&self) -> f64 {
+        self.pitch
+    }
+
+    pub fn gain(&self) -> Option<f32> {
+        self.gain
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cli::{PositionWeight, Salts};
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+    use rand::SeedableRng;
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0)
+    }
 
     #[test]
     fn slice_next() {
         let mut slice = SegmentSlice::new(
             vec![Segment::new(0, false, false), Segment::new(16, true, true)],
+            0,
             1,
             0.,
         );
@@ -212,12 +956,10 @@ mod tests {
                 vec![Segment::new(0, true, true), Segment::new(16, false, false)],
             ],
             index: 0,
-            segment_len: 1,
+            slots: vec![(0, 1), (1, 1)],
         };
 
-        let mut slices = vec![];
-        slices.push(layout.next().unwrap());
-        slices.push(layout.next().unwrap());
+        let mut slices = [layout.next().unwrap(), layout.next().unwrap()];
 
         assert_eq!(layout.next(), None);
         assert_eq!(slices[0].next(), Some(Segment::new(0, false, false)));
@@ -228,6 +970,73 @@ mod tests {
         assert_eq!(slices[1].next(), None);
     }
 
+    #[test]
+    fn layout_coverage() {
+        let layout = SegmentLayout {
+            segments: vec![
+                vec![
+                    Segment::new(0, true, false),
+                    Segment::new(0, false, false),
+                    Segment::new(48, false, true),
+                ],
+                vec![
+                    Segment::new(0, true, false),
+                    Segment::new(16, false, false).with_freeze(true),
+                    Segment::new(48, false, false),
+                ],
+            ],
+            index: 0,
+            slots: vec![(0, 16), (16, 32), (48, 16)],
+        };
+
+        assert_eq!(
+            layout.coverage(),
+            [
+                ("moved", 0.25),
+                ("reversed", 0.25),
+                ("silenced", 0.125),
+                ("frozen", 0.25),
+                ("granular", 0.),
+                ("beat repeated", 0.),
+                ("widened", 0.),
+                ("gained", 0.),
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_next_fades() {
+        let mut layout = SegmentLayout {
+            segments: vec![
+                vec![
+                    Segment::new(0, false, false),
+                    Segment::new(16, false, true),
+                    Segment::new(32, false, false),
+                ],
+                vec![
+                    Segment::new(0, false, true),
+                    Segment::new(16, false, false),
+                    Segment::new(32, false, false),
+                ],
+            ],
+            index: 0,
+            slots: vec![(0, 16), (16, 16), (32, 16)],
+        };
+
+        assert_eq!(
+            layout.next().unwrap().fades(),
+            [(false, true), (false, false)]
+        );
+        assert_eq!(
+            layout.next().unwrap().fades(),
+            [(false, false), (true, false)]
+        );
+        assert_eq!(
+            layout.next().unwrap().fades(),
+            [(true, false), (false, false)]
+        );
+    }
+
     #[test]
     fn segment_len_test() {
         assert_eq!(SegmentLayout::segment_len(48000, 120., 0.25), 24000);
@@ -255,13 +1064,15 @@ mod tests {
             max_swap: 1,
             max_repeat: 1,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let mut channel = SegmentLayout::build_channel(cli_config, wav_config).into_iter();
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
 
         assert_eq!(channel.next(), Some(Segment::new(0, false, false)));
         assert_eq!(channel.next(), Some(Segment::new(3600, false, false)));
@@ -284,13 +1095,15 @@ mod tests {
             max_swap: 1,
             max_repeat: 1,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let mut channel = SegmentLayout::build_channel(cli_config, wav_config).into_iter();
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
 
         assert_eq!(channel.next(), Some(Segment::new(0, false, true)));
         assert_eq!(channel.next(), Some(Segment::new(3600, false, true)));
@@ -301,6 +1114,59 @@ mod tests {
         assert_eq!(channel.next(), None);
     }
 
+    #[test]
+    fn channel_build_gain() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            prob_gain: 1.,
+            gain_amount: 0.25,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+
+        assert_eq!(channel.len(), 6);
+        assert!(channel.iter().all(|segment| segment.gain() == Some(0.25)));
+    }
+
+    #[test]
+    fn channel_build_freeze() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            segment_length: 0.0625,
+            prob_silence: 0.,
+            prob_swap: 0.,
+            prob_reverse: 0.,
+            prob_repeat: 0.,
+            max_swap: 1,
+            max_repeat: 1,
+            each_channel_separately: true,
+            prob_freeze: 1.,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 7200,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
+
+        assert_eq!(
+            channel.next(),
+            Some(Segment::new(0, false, false).with_freeze(true))
+        );
+        assert_eq!(
+            channel.next(),
+            Some(Segment::new(3600, false, false).with_freeze(true))
+        );
+        assert_eq!(channel.next(), None);
+    }
+
     #[test]
     fn channel_build_swap() {
         let cli_config = CliConfig {
@@ -313,13 +1179,15 @@ mod tests {
             max_swap: 1,
             max_repeat: 1,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let mut channel = SegmentLayout::build_channel(cli_config, wav_config).into_iter();
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
 
         assert_eq!(channel.next(), Some(Segment::new(3600, false, false)));
         assert_eq!(channel.next(), Some(Segment::new(7200, false, false)));
@@ -342,13 +1210,15 @@ mod tests {
             max_swap: 1,
             max_repeat: 1,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let mut channel = SegmentLayout::build_channel(cli_config, wav_config).into_iter();
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
 
         assert_eq!(channel.next(), Some(Segment::new(0, true, false)));
         assert_eq!(channel.next(), Some(Segment::new(3600, true, false)));
@@ -371,13 +1241,15 @@ mod tests {
             max_swap: 1,
             max_repeat: 1,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let mut channel = SegmentLayout::build_channel(cli_config, wav_config).into_iter();
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
 
         assert_eq!(channel.next(), Some(Segment::new(0, false, false)));
         assert_eq!(channel.next(), Some(Segment::new(0, false, false)));
@@ -400,13 +1272,15 @@ mod tests {
             max_swap: 1,
             max_repeat: 1,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let mut channel = SegmentLayout::build_channel(cli_config, wav_config).into_iter();
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
 
         assert_eq!(channel.next(), Some(Segment::new(3600, false, false)));
         assert_eq!(channel.next(), Some(Segment::new(3600, false, false)));
@@ -417,6 +1291,753 @@ mod tests {
         assert_eq!(channel.next(), None);
     }
 
+    #[test]
+    fn channel_build_grid_offset() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            segment_length: 0.0625,
+            prob_silence: 0.,
+            prob_swap: 0.,
+            prob_reverse: 0.,
+            prob_repeat: 0.,
+            max_swap: 1,
+            max_repeat: 1,
+            each_channel_separately: true,
+            grid_offset: 1000,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
+
+        assert_eq!(channel.next(), Some(Segment::new(0, false, false)));
+        assert_eq!(channel.next(), Some(Segment::new(1000, false, false)));
+        assert_eq!(channel.next(), Some(Segment::new(4600, false, false)));
+        assert_eq!(channel.next(), Some(Segment::new(8200, false, false)));
+        assert_eq!(channel.next(), Some(Segment::new(11800, false, false)));
+        assert_eq!(channel.next(), Some(Segment::new(15400, false, false)));
+        assert_eq!(channel.next(), Some(Segment::new(19000, false, false)));
+        assert_eq!(channel.next(), None);
+    }
+
+    #[test]
+    fn layout_build_grid_offset() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            segment_length: 0.0625,
+            prob_silence: 0.,
+            prob_swap: 0.,
+            prob_reverse: 0.,
+            prob_repeat: 0.,
+            max_swap: 1,
+            max_repeat: 1,
+            each_channel_separately: false,
+            grid_offset: 4600,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let mut layout = SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng());
+        let lead = layout.next().unwrap();
+        let first = layout.next().unwrap();
+
+        assert_eq!((lead.offset(), lead.segment_len()), (0, 1000));
+        assert_eq!((first.offset(), first.segment_len()), (1000, 3600));
+        assert_eq!(layout.count(), 5);
+    }
+
+    #[test]
+    fn channel_build_out_of_range() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            segment_length: 0.0625,
+            prob_silence: -0.5,
+            prob_swap: f64::NAN,
+            prob_reverse: 1.5,
+            prob_repeat: f64::INFINITY,
+            max_swap: 1,
+            max_repeat: 1,
+            each_channel_separately: true,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
+
+        assert_eq!(channel.next(), Some(Segment::new(0, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(0, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(7200, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(7200, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(14400, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(18000, true, false)));
+        assert_eq!(channel.next(), None);
+    }
+
+    #[test]
+    fn layout_build_start() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            segment_length: 0.0625,
+            prob_silence: 0.,
+            prob_swap: 0.,
+            prob_reverse: 0.,
+            prob_repeat: 0.,
+            max_swap: 1,
+            max_repeat: 1,
+            each_channel_separately: false,
+            start: 0.1,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let mut layout = SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng());
+        let mut first = layout.next().unwrap();
+
+        assert_eq!(first.offset(), 4800);
+        assert_eq!(first.next(), Some(Segment::new(4800, false, false)));
+        assert_eq!(layout.last().unwrap().offset(), 19200);
+    }
+
+    #[test]
+    fn channel_build_clean_tail() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            segment_length: 0.0625,
+            prob_silence: 0.,
+            prob_swap: 1.,
+            prob_reverse: 1.,
+            prob_repeat: 0.,
+            max_swap: 1,
+            max_repeat: 1,
+            each_channel_separately: true,
+            clean_tail: 0.1,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let mut channel =
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng()).into_iter();
+
+        assert_eq!(channel.next(), Some(Segment::new(3600, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(7200, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(10800, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(0, true, false)));
+        assert_eq!(channel.next(), Some(Segment::new(14400, false, false)));
+        assert_eq!(channel.next(), Some(Segment::new(18000, false, false)));
+        assert_eq!(channel.next(), None);
+    }
+
+    #[test]
+    fn layout_build_transient() {
+        let cli_config = CliConfig {
+            start: 0.0625,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let layout = SegmentLayout::build_transient(
+            cli_config,
+            wav_config,
+            &[1000, 5000, 12000],
+            &[],
+            &[],
+            &mut rng(),
+        );
+
+        assert_eq!(
+            layout
+                .map(|mut slice| (slice.offset(), slice.segment_len(), slice.next().unwrap()))
+                .collect::<Vec<_>>(),
+            [
+                (3000, 2000, Segment::new(3000, false, false)),
+                (5000, 7000, Segment::new(5000, false, false)),
+                (12000, 7800, Segment::new(12000, false, false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_from_file() {
+        let dir = TempDir::new().unwrap();
+        let cache = dir.child("layout.json");
+        let cli_config = CliConfig {
+            prob_swap: 0.5,
+            prob_reverse: 0.5,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let layout = SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng());
+        layout.save(cache.path()).unwrap();
+
+        let loaded = SegmentLayout::from_file(cache.path(), wav_config).unwrap();
+        assert_eq!(loaded.segments, layout.segments);
+        assert_eq!(loaded.slots, layout.slots);
+
+        let shorter = WavConfig {
+            duration: 7200,
+            ..wav_config
+        };
+        assert_eq!(
+            SegmentLayout::from_file(cache.path(), shorter)
+                .unwrap_err()
+                .to_string(),
+            "cached layout does not match input"
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn channel_build_curve() {
+        let cli_config = CliConfig {
+            prob_reverse: 1.,
+            segment_length: 1. / 64.,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 4_800_000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[0., 1.], &mut rng());
+        let (first, second) = channel.split_at(channel.len() / 2);
+        let reversed = |half: &[Segment]| half.iter().filter(|segment| segment.reverse()).count();
+
+        assert!(reversed(first) < reversed(second));
+        assert!(!channel[0].reverse());
+        assert!(channel.last().unwrap().reverse());
+    }
+
+    #[test]
+    fn channel_build_position_weight_bell() {
+        let cli_config = CliConfig {
+            prob_reverse: 1.,
+            segment_length: 1. / 64.,
+            position_weight: PositionWeight::Bell,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 4_800_000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+        let third = channel.len() / 3;
+        let reversed = |part: &[Segment]| part.iter().filter(|segment| segment.reverse()).count();
+        let (start, middle, end) = (
+            reversed(&channel[..third]),
+            reversed(&channel[third..2 * third]),
+            reversed(&channel[2 * third..]),
+        );
+
+        assert!(middle > 2 * start, "{start} {middle} {end}");
+        assert!(middle > 2 * end, "{start} {middle} {end}");
+    }
+
+    #[test]
+    fn layout_is_passthrough() {
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let layout = SegmentLayout::build(CliConfig::default(), wav_config, &[], &[], &mut rng());
+        let cli_config = CliConfig {
+            prob_reverse: 1.,
+            ..Default::default()
+        };
+
+        assert!(layout.is_passthrough());
+        assert!(layout.passthrough().is_passthrough());
+        assert!(
+            !SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng()).is_passthrough()
+        );
+    }
+
+    #[test]
+    fn layout_build_seed() {
+        let cli_config = CliConfig {
+            prob_reverse: 0.5,
+            prob_swap: 0.5,
+            each_channel_separately: true,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 480_000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let build = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng).segments
+        };
+        let segments = build(7);
+
+        assert_eq!(segments, build(7));
+        assert_ne!(segments, build(8));
+        assert_ne!(segments[0], segments[1]);
+    }
+
+    #[test]
+    fn build_channel_salts() {
+        let wav_config = WavConfig {
+            duration: 480_000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let build = |salt| {
+            let mut salts = Salts::default();
+            salts.set(Effect::Reverse, salt);
+            let cli_config = CliConfig {
+                prob_silence: 0.5,
+                prob_reverse: 0.5,
+                prob_freeze: 0.5,
+                prob_swap: 0.5,
+                salts,
+                ..Default::default()
+            };
+            SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng())
+        };
+        let (first, second) = (build(0), build(1));
+        let flags = |channel: &[Segment], flag: fn(&Segment) -> bool| -> Vec<_> {
+            channel
+                .iter()
+                .map(|segment| (segment.offset(), flag(segment)))
+                .collect()
+        };
+
+        assert_eq!(
+            flags(&first, Segment::silence),
+            flags(&second, Segment::silence)
+        );
+        assert_eq!(
+            flags(&first, Segment::freeze),
+            flags(&second, Segment::freeze)
+        );
+        assert_ne!(
+            flags(&first, Segment::reverse),
+            flags(&second, Segment::reverse)
+        );
+    }
+
+    #[test]
+    fn layout_variance() {
+        let wav_config = WavConfig {
+            duration: 480_000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let variance = |cli_config| {
+            let mut rng = rng();
+            let layouts: Vec<_> = (0..4)
+                .map(|_| SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng))
+                .collect();
+            SegmentLayout::variance(&layouts)
+        };
+
+        assert_eq!(variance(CliConfig::default()), 0.);
+        assert!(
+            variance(CliConfig {
+                prob_reverse: 0.5,
+                ..Default::default()
+            }) > 0.
+        );
+    }
+
+    #[test]
+    fn channel_build_sidechain() {
+        let cli_config = CliConfig {
+            prob_reverse: 1.,
+            segment_length: 1. / 64.,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 480_000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        // Silent in its first 133 segments of 1800 samples, loud in the rest
+        let sidechain: Vec<f64> = (0..480_000)
+            .map(|i| if i < 239_400 { 0. } else { 0.5 })
+            .collect();
+        let curve = SegmentLayout::sidechain_curve(cli_config, wav_config, &[sidechain]);
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &curve, &mut rng());
+        let (first, second) = channel.split_at(channel.len() / 2);
+        let reversed = |half: &[Segment]| half.iter().filter(|segment| segment.reverse()).count();
+
+        assert_eq!(curve.len(), channel.len());
+        assert_eq!(reversed(first), 0);
+        assert_eq!(reversed(second), second.len());
+    }
+
+    #[test]
+    fn channel_build_on_beats() {
+        let cli_config = CliConfig {
+            tempo: 120.,
+            prob_reverse: 1.,
+            on_beats: true,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+
+        assert_eq!(
+            channel.iter().map(Segment::reverse).collect::<Vec<_>>(),
+            [true, false, false, false, true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn beats_uneven_tempo() {
+        let cli_config = CliConfig {
+            tempo: 130.,
+            on_beats: true,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 8 * 5088,
+            sample_rate: 44100,
+            channels: 1,
+        };
+        let offsets: Vec<u32> = (0..8).map(|i| i * 5088).collect();
+
+        assert_eq!(
+            SegmentLayout::beats(cli_config, wav_config, &offsets),
+            [true, false, false, false, true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn channel_build_min_gap() {
+        let cli_config = CliConfig {
+            prob_reverse: 0.5,
+            prob_swap: 0.5,
+            min_gap: 3,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 480_000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let offsets: Vec<u32> = (0..67).map(|i| i * 7200).collect();
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+        let glitched: Vec<usize> = channel
+            .iter()
+            .zip(&offsets)
+            .enumerate()
+            .filter(|(_, (segment, &offset))| **segment != Segment::new(offset, false, false))
+            .map(|(i, _)| i)
+            .collect();
+
+        assert!(glitched.len() > 1);
+        assert!(glitched.windows(2).all(|pair| pair[1] - pair[0] > 3));
+    }
+
+    #[test]
+    fn channel_build_repeat_pitch() {
+        let cli_config = CliConfig {
+            prob_repeat: 1.,
+            max_repeat: 3,
+            repeat_pitch: 2.,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 480_000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+
+        let mut copies = 0;
+        for (i, segment) in channel.iter().enumerate() {
+            if i > 0 && segment.offset() == channel[i - 1].offset() {
+                copies += 1;
+            } else {
+                copies = 0;
+            }
+            assert_eq!(segment.pitch(), 2. * copies as f64);
+        }
+        assert!(channel.iter().any(|segment| segment.pitch() > 0.));
+    }
+
+    #[test]
+    fn channel_build_budget() {
+        let cli_config = CliConfig {
+            prob_reverse: 0.5,
+            prob_silence: 0.1,
+            budget: Some(5),
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 480_000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+
+        assert_eq!(
+            channel
+                .iter()
+                .filter(|segment| segment.reverse() || segment.silence())
+                .count(),
+            5
+        );
+    }
+
+    #[test]
+    fn channel_build_budget_larger() {
+        let cli_config = CliConfig {
+            prob_freeze: 1.,
+            budget: Some(1000),
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+
+        assert!(channel.iter().all(Segment::freeze));
+    }
+
+    #[test]
+    fn channel_build_budget_clean_tail() {
+        let cli_config = CliConfig {
+            prob_reverse: 1.,
+            budget: Some(1000),
+            clean_tail: 0.15,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+        // Seven segments of 7200 samples, the last two of which end in the clean tail
+        let (glitched, tail) = channel.split_at(5);
+
+        assert_eq!(channel.len(), 7);
+        assert!(glitched.iter().all(Segment::reverse));
+        assert!(!tail.iter().any(Segment::reverse));
+    }
+
+    #[test]
+    fn channel_build_budget_on_beats() {
+        let cli_config = CliConfig {
+            tempo: 120.,
+            prob_reverse: 1.,
+            budget: Some(3),
+            on_beats: true,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 96000,
+            sample_rate: 48000,
+            channels: 1,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+
+        assert_eq!(
+            channel.iter().filter(|segment| segment.reverse()).count(),
+            3
+        );
+    }
+
+    #[test]
+    fn curve_at_interpolates() {
+        assert_eq!(SegmentLayout::curve_at(&[], 0.3), 1.);
+        assert_eq!(SegmentLayout::curve_at(&[0.5], 0.3), 0.5);
+        assert_eq!(SegmentLayout::curve_at(&[0., 1.], 0.25), 0.25);
+        assert_eq!(SegmentLayout::curve_at(&[0., 1., 0.], 0.75), 0.5);
+        assert_eq!(SegmentLayout::curve_at(&[0., 1.], 1.), 1.);
+    }
+
+    #[test]
+    fn read_curve() {
+        let dir = TempDir::new().unwrap();
+        let curve = dir.child("curve.txt");
+        let empty = dir.child("empty.txt");
+        let invalid = dir.child("invalid.txt");
+        curve.write_str("0.5, 2\n-1\n").unwrap();
+        empty.write_str("\n").unwrap();
+        invalid.write_str("0.5 high").unwrap();
+
+        assert_eq!(SegmentLayout::read_curve(curve).unwrap(), [0.5, 1., 0.]);
+        assert_eq!(
+            SegmentLayout::read_curve(empty).unwrap_err().to_string(),
+            "probability curve is empty"
+        );
+        assert_eq!(
+            SegmentLayout::read_curve(invalid).unwrap_err().to_string(),
+            "when reading probability curve value `high`"
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn read_prob_matrix() {
+        let dir = TempDir::new().unwrap();
+        let matrix = dir.child("matrix.txt");
+        matrix
+            .write_str("0 0 1 0 0 0 0 0 0\n\n0, 0, 0.5, 0, 0, 0, 0, 0, 2\n")
+            .unwrap();
+        let short = dir.child("short.txt");
+        short.write_str("0 0 1\n0 0 1\n").unwrap();
+
+        let configs = SegmentLayout::read_prob_matrix(&matrix, CliConfig::default(), 2).unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].prob_reverse, 1.);
+        assert_eq!(configs[1].prob_reverse, 0.5);
+        assert_eq!(configs[1].prob_gain, 1.);
+        assert_eq!(
+            SegmentLayout::read_prob_matrix(&matrix, CliConfig::default(), 1)
+                .unwrap_err()
+                .to_string(),
+            "probability matrix has 2 rows for 1 channels"
+        );
+        assert_eq!(
+            SegmentLayout::read_prob_matrix(&short, CliConfig::default(), 2)
+                .unwrap_err()
+                .to_string(),
+            "probability matrix row 0 has 3 values for 9 effects"
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn layout_percentage_monotonic() {
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let layouts = [
+            SegmentLayout::build(CliConfig::default(), wav_config, &[], &[], &mut rng()),
+            SegmentLayout::build(
+                CliConfig {
+                    grid_offset: 4600,
+                    start: 0.05,
+                    prob_repeat: 1.,
+                    ..Default::default()
+                },
+                wav_config,
+                &[],
+                &[],
+                &mut rng(),
+            ),
+            SegmentLayout::build_transient(
+                CliConfig::default(),
+                wav_config,
+                &[1000, 5000],
+                &[],
+                &[],
+                &mut rng(),
+            ),
+        ];
+
+        for layout in layouts {
+            let percentages: Vec<_> = layout.map(|slice| slice.percentage()).collect();
+            assert!(percentages.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(percentages.iter().all(|p| (0.0..=100.).contains(p)));
+            assert_eq!(percentages.last(), Some(&100.));
+        }
+    }
+
+    #[test]
+    fn channel_build_envelope() {
+        let mut cli_config = CliConfig::default();
+        cli_config.envelopes.set(Effect::Reverse, (1., 0.));
+        cli_config.envelopes.set(Effect::Silence, (0., 1.));
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let channel = SegmentLayout::build_channel(cli_config, wav_config, &[], &mut rng());
+
+        assert_eq!(channel.first(), Some(&Segment::new(0, true, false)));
+        assert_eq!(channel.last(), Some(&Segment::new(14400, false, true)));
+    }
+
+    #[test]
+    fn layout_build_widen() {
+        let cli_config = CliConfig {
+            tempo: 200.,
+            segment_length: 0.0625,
+            prob_silence: 0.,
+            prob_swap: 0.,
+            prob_reverse: 0.,
+            prob_repeat: 0.,
+            max_swap: 1,
+            max_repeat: 1,
+            each_channel_separately: false,
+            prob_widen: 1.,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 19800,
+            sample_rate: 48000,
+            channels: 2,
+        };
+
+        for mut slice in SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng()) {
+            assert!(!slice.next().unwrap().invert());
+            assert!(slice.next().unwrap().invert());
+        }
+    }
+
+    #[test]
+    fn layout_build_widen_clean_tail() {
+        let cli_config = CliConfig {
+            prob_widen: 1.,
+            clean_tail: 0.15,
+            ..Default::default()
+        };
+        let wav_config = WavConfig {
+            duration: 48000,
+            sample_rate: 48000,
+            channels: 2,
+        };
+        let layout = SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng());
+        let inverted: Vec<bool> = layout.segments[1].iter().map(Segment::invert).collect();
+
+        assert_eq!(inverted, [true, true, true, true, true, false, false]);
+        assert!(!layout.segments[0].iter().any(Segment::invert));
+    }
+
     #[test]
     fn layout_build_same() {
         let cli_config = CliConfig {
@@ -429,14 +2050,15 @@ mod tests {
             max_swap: 1,
             max_repeat: 1,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let layout = SegmentLayout::build(cli_config, wav_config);
-        let mut channels = vec![vec![], vec![]];
+        let layout = SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng());
+        let mut channels = [vec![], vec![]];
 
         for slice in layout {
             for (i, segment) in slice.enumerate() {
@@ -459,14 +2081,15 @@ mod tests {
             max_swap: 5,
             max_repeat: 5,
             each_channel_separately: true,
+            ..Default::default()
         };
         let wav_config = WavConfig {
             duration: 19800,
             sample_rate: 48000,
             channels: 2,
         };
-        let layout = SegmentLayout::build(cli_config, wav_config);
-        let mut channels = vec![vec![], vec![]];
+        let layout = SegmentLayout::build(cli_config, wav_config, &[], &[], &mut rng());
+        let mut channels = [vec![], vec![]];
 
         for slice in layout {
             for (i, segment) in slice.enumerate() {
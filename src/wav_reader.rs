@@ -1,11 +1,68 @@
-use crate::segment_layout::SegmentSlice;
-use anyhow::{Context, Result};
-use std::fs::File;
-use std::io::BufReader;
+use crate::cli::{CliConfig, FadeCurve, IntMath};
+use crate::effects;
+use crate::sample_math::SampleMath;
+use crate::segment_layout::{Segment, SegmentSlice};
+use anyhow::{anyhow, Context, Result};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
 
 pub struct WavReader {
-    reader: hound::WavReader<BufReader<File>>,
+    reader: hound::WavReader<Source>,
+    /// Inputs read after `reader` as one continuous stream
+    appended: Vec<hound::WavReader<Source>>,
+    level: Option<f64>,
+    /// Gain that level matching applied to the last slice read
+    level_gain: f64,
+    /// Weights of the input channels in each channel read, none to read them as they are
+    matrix: Vec<Vec<f64>>,
+    /// Channel read into each output channel, none to keep them in order
+    order: Vec<u16>,
+    /// Generator of the randomness of effects applied while reading
+    rng: StdRng,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReadMode {
+    /// Buffered reads with the given buffer size in bytes
+    Buffered(usize),
+    /// Reads from the memory-mapped file
+    #[cfg(feature = "mmap")]
+    Mapped,
+}
+
+impl Default for ReadMode {
+    fn default() -> ReadMode {
+        ReadMode::Buffered(8 * 1024)
+    }
+}
+
+enum Source {
+    Buffered(BufReader<File>),
+    #[cfg(feature = "mmap")]
+    Mapped(io::Cursor<memmap2::Mmap>),
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Buffered(reader) => reader.read(buf),
+            #[cfg(feature = "mmap")]
+            Source::Mapped(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::Buffered(reader) => reader.seek(pos),
+            #[cfg(feature = "mmap")]
+            Source::Mapped(cursor) => cursor.seek(pos),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -16,9 +73,134 @@ pub struct WavConfig {
 }
 
 impl WavReader {
+    /// Length in seconds of the fades windowing a reversed segment
+    const REVERSE_FADE: f64 = 0.005;
+    /// Length in seconds of the ramps of silenced segments
+    const SILENCE_RAMP: f64 = 0.001;
+
     pub fn open<P: AsRef<Path>>(path: P) -> Result<WavReader> {
-        let reader = hound::WavReader::open(path).context("when opening input file")?;
-        Ok(WavReader { reader })
+        Self::open_with(path, ReadMode::default())
+    }
+
+    pub fn open_with<P: AsRef<Path>>(path: P, mode: ReadMode) -> Result<WavReader> {
+        Ok(WavReader {
+            reader: Self::open_source(path, mode)?,
+            appended: vec![],
+            level: None,
+            level_gain: 1.,
+            matrix: vec![],
+            order: vec![],
+            rng: StdRng::from_entropy(),
+        })
+    }
+
+    fn open_source<P: AsRef<Path>>(path: P, mode: ReadMode) -> Result<hound::WavReader<Source>> {
+        let file = File::open(path).context("when opening input file")?;
+        let source = match mode {
+            ReadMode::Buffered(capacity) => {
+                Source::Buffered(BufReader::with_capacity(capacity, file))
+            }
+            // SAFETY: the input is only read, and is expected not to be modified while processing
+            #[cfg(feature = "mmap")]
+            ReadMode::Mapped => Source::Mapped(io::Cursor::new(
+                unsafe { memmap2::Mmap::map(&file) }.context("when mapping input file")?,
+            )),
+        };
+        hound::WavReader::new(source).context("when opening input file")
+    }
+
+    /// Appends inputs with the same spec, to be read as one continuous stream
+    pub fn with_concat<P: AsRef<Path>>(mut self, paths: &[P], mode: ReadMode) -> Result<WavReader> {
+        for path in paths {
+            let reader = Self::open_source(path, mode)?;
+            if reader.spec() != self.reader.spec() {
+                return Err(anyhow!(
+                    "`{}` does not have the same spec as the input",
+                    path.as_ref().display()
+                ));
+            }
+            self.appended.push(reader);
+        }
+        Ok(self)
+    }
+
+    /// Averages each group of input channels into a single channel
+    pub fn with_merge(mut self, merge: Vec<Vec<u16>>) -> Result<WavReader> {
+        let channels = self.reader.spec().channels;
+        if let Some(channel) = merge.iter().flatten().find(|&&channel| channel >= channels) {
+            return Err(anyhow!("merged channel {channel} is not in 0..{channels}"));
+        }
+        self.matrix = merge
+            .iter()
+            .map(|group| {
+                let mut row = vec![0.; channels as usize];
+                for &channel in group {
+                    row[channel as usize] += 1. / group.len() as f64;
+                }
+                row
+            })
+            .collect();
+        Ok(self)
+    }
+
+    /// Mixes the input channels into one channel per row of `matrix`, with a weight per
+    /// input channel in each row
+    pub fn with_matrix(mut self, matrix: Vec<Vec<f64>>) -> Result<WavReader> {
+        let channels = self.reader.spec().channels as usize;
+        if let Some(row) = matrix.iter().find(|row| row.len() != channels) {
+            return Err(anyhow!(
+                "matrix has {} columns for {channels} input channels",
+                row.len()
+            ));
+        }
+        self.matrix = matrix;
+        Ok(self)
+    }
+
+    /// Seeds the randomness of effects applied while reading, so that a seed reproduces them
+    pub fn with_seed(mut self, seed: u64) -> WavReader {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Outputs the channels read in `order`, which may repeat channels but not change their
+    /// number
+    pub fn with_order(mut self, order: Vec<u16>) -> Result<WavReader> {
+        let channels = self.spec().channels;
+        if !order.is_empty() && order.len() != channels as usize {
+            return Err(anyhow!(
+                "channel order has {} channels for {channels} channels",
+                order.len()
+            ));
+        }
+        if let Some(channel) = order.iter().find(|&&channel| channel >= channels) {
+            return Err(anyhow!("ordered channel {channel} is not in 0..{channels}"));
+        }
+        self.order = order;
+        Ok(self)
+    }
+
+    /// Reads a mixing matrix with one row per line, its values separated by whitespace or commas
+    pub fn read_matrix<P: AsRef<Path>>(path: P) -> Result<Vec<Vec<f64>>> {
+        let text = fs::read_to_string(path).context("when reading matrix")?;
+        let matrix = text
+            .lines()
+            .map(|line| {
+                line.split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|value| !value.is_empty())
+                    .map(|value| {
+                        value
+                            .parse::<f64>()
+                            .with_context(|| format!("when reading matrix value `{value}`"))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .filter(|row| !row.as_ref().is_ok_and(Vec::is_empty))
+            .collect::<Result<Vec<_>>>()?;
+        if matrix.is_empty() {
+            return Err(anyhow!("matrix is empty"));
+        }
+        Ok(matrix)
     }
 
     pub fn config(&self) -> WavConfig {
@@ -26,8 +208,8 @@ impl WavReader {
             channels,
             sample_rate,
             ..
-        } = self.reader.spec();
-        let duration = self.reader.duration();
+        } = self.spec();
+        let duration = self.duration();
 
         WavConfig {
             duration,
@@ -36,46 +218,463 @@ impl WavReader {
         }
     }
 
+    fn duration(&self) -> u32 {
+        self.reader.duration()
+            + self
+                .appended
+                .iter()
+                .map(hound::WavReader::duration)
+                .sum::<u32>()
+    }
+
     pub fn spec(&self) -> hound::WavSpec {
-        self.reader.spec()
+        let mut spec = self.reader.spec();
+        if !self.matrix.is_empty() {
+            spec.channels = self.matrix.len() as u16;
+        }
+        spec
     }
 
-    pub fn read<S: hound::Sample + std::clone::Clone + From<i16>>(
+    pub fn read<S: SampleMath>(
         &mut self,
         slice: &mut SegmentSlice,
+        cli_config: CliConfig,
     ) -> Result<Vec<S>> {
         let mut channels: Vec<Vec<S>> = vec![];
         let segment_len = slice.segment_len();
+        let slice_offset = slice.offset();
+        // Slots of differing lengths can hold a segment that reaches the end of the input
+        // before filling them, so every channel is padded to the length of the slot
+        let slot_len = segment_len.min(self.duration().saturating_sub(slice_offset) as usize);
+        let segments = slice.segments().to_vec();
+        let fades = slice.fades().to_vec();
+        let fade_len =
+            (cli_config.silence_fade * self.reader.spec().sample_rate as f64).round() as usize;
+        let bits = self.reader.spec().bits_per_sample;
+        let mode = cli_config.int_math;
 
         for (i, segment) in slice.enumerate() {
-            if segment.silence() {
-                let channel = vec![
-                    0.into();
-                    (self.reader.duration() as usize - segment.offset() as usize)
-                        .min(segment_len)
-                ];
-                channels.push(channel);
+            let channel = if segment.silence() && cli_config.silence_ramp {
+                // Start and end at the input so that the cut to zero does not click
+                let mut channel = self
+                    .read_channel(i, segment_len, segment.offset(), mode)
+                    .context("when reading from input file")?;
+                let len = channel.len();
+                let ramp_len = ((Self::SILENCE_RAMP * self.reader.spec().sample_rate as f64)
+                    as usize)
+                    .min(len / 2);
+                channel[ramp_len..len - ramp_len].fill(0.into());
+                let curve = FadeCurve::Linear;
+                effects::fade_out(&mut channel[..ramp_len], ramp_len, curve, bits, mode);
+                effects::fade_in(&mut channel[len - ramp_len..], ramp_len, curve, bits, mode);
+                channel
+            } else if segment.silence() {
+                vec![0.into(); slot_len]
             } else {
                 let mut channel = self
-                    .read_segment(i, segment_len, segment.offset())
+                    .read_channel(i, segment_len, segment.offset(), mode)
                     .context("when reading from input file")?;
 
-                if segment.reverse() {
+                if segment.freeze() {
+                    let grain_len = self.reader.spec().sample_rate as usize / 25;
+                    effects::freeze(&mut channel, grain_len, bits, mode, &mut self.rng);
+                }
+                if segment.granular() {
+                    let grain_len =
+                        (cli_config.grain_len * self.reader.spec().sample_rate as f64) as usize;
+                    effects::granular(
+                        &mut channel,
+                        grain_len,
+                        cli_config.grain_density,
+                        bits,
+                        mode,
+                        &mut self.rng,
+                    );
+                }
+                if segment.beat_repeat() {
+                    effects::beat_repeat(&mut channel, cli_config.divisions, &mut self.rng);
+                }
+                if segment.pitch() != 0. && !channel.is_empty() {
+                    effects::pitch(&mut channel, segment.pitch(), bits, mode);
+                }
+                if segment.reverse()
+                    && cli_config
+                        .reverse_above
+                        .is_none_or(|threshold| self.dbfs(&channel) > threshold)
+                {
                     channel.reverse();
+                    if cli_config.reverse_window {
+                        let fade_len =
+                            ((Self::REVERSE_FADE * self.reader.spec().sample_rate as f64) as usize)
+                                .min(channel.len() / 2);
+                        effects::fade_in(&mut channel, fade_len, cli_config.fade_curve, bits, mode);
+                        effects::fade_out(
+                            &mut channel,
+                            fade_len,
+                            cli_config.fade_curve,
+                            bits,
+                            mode,
+                        );
+                    }
+                }
+                if let Some(gain) = segment.gain() {
+                    for sample in &mut channel {
+                        let value = SampleMath::to_f64(*sample) * gain as f64;
+                        *sample = S::from_f64_clamped(value, bits, mode);
+                    }
+                }
+                if cli_config.segment_dc_block {
+                    effects::remove_dc(&mut channel, bits, mode);
+                }
+                if let Some(&(silent_before, silent_after)) = fades.get(i) {
+                    if silent_before {
+                        effects::fade_in(&mut channel, fade_len, cli_config.fade_curve, bits, mode);
+                    }
+                    if silent_after {
+                        effects::fade_out(
+                            &mut channel,
+                            fade_len,
+                            cli_config.fade_curve,
+                            bits,
+                            mode,
+                        );
+                    }
+                }
+                channel
+            };
+            channels.push(channel);
+        }
+        for channel in &mut channels {
+            channel.resize(slot_len, 0.into());
+        }
+
+        self.post_process(
+            &mut channels,
+            &segments,
+            slice_offset,
+            slot_len,
+            cli_config,
+            true,
+        )?;
+        Ok(self.interleave(channels, cli_config))
+    }
+
+    /// Reads the `len` frames that would follow `slice` if its segments played on, going on
+    /// backwards for reversed segments and staying silent for silenced ones, processed as
+    /// `read` processes them after their effects, to crossfade with the next slice
+    pub fn read_tail<S: SampleMath>(
+        &mut self,
+        slice: &SegmentSlice,
+        cli_config: CliConfig,
+        len: usize,
+    ) -> Result<Vec<S>> {
+        let segment_len = slice.segment_len();
+        let bits = self.reader.spec().bits_per_sample;
+        let mode = cli_config.int_math;
+        let mut channels: Vec<Vec<S>> = vec![];
+
+        for (i, segment) in slice.segments().iter().enumerate() {
+            let mut channel = if segment.silence() {
+                vec![]
+            } else if segment.reverse() {
+                let start = segment.offset().saturating_sub(len as u32);
+                let mut channel = self
+                    .read_channel(i, (segment.offset() - start) as usize, start, mode)
+                    .context("when reading from input file")?;
+                channel.reverse();
+                channel
+            } else {
+                self.read_channel(i, len, segment.offset() + segment_len as u32, mode)
+                    .context("when reading from input file")?
+            };
+            if let Some(gain) = segment.gain() {
+                for sample in &mut channel {
+                    let value = SampleMath::to_f64(*sample) * gain as f64;
+                    *sample = S::from_f64_clamped(value, bits, mode);
+                }
+            }
+            channel.resize(len, 0.into());
+            channels.push(channel);
+        }
+
+        self.post_process(
+            &mut channels,
+            slice.segments(),
+            slice.offset() + segment_len as u32,
+            len,
+            cli_config,
+            false,
+        )?;
+        Ok(self.interleave(channels, cli_config))
+    }
+
+    /// Runs `channels`, read for `segments`, through what follows their effects, blending
+    /// in the `len` input frames at `dry_offset`. Level matching measures `channels` if
+    /// `measure_level`, or else continues the gain of the last slice measured
+    fn post_process<S: SampleMath>(
+        &mut self,
+        channels: &mut [Vec<S>],
+        segments: &[Segment],
+        dry_offset: u32,
+        len: usize,
+        cli_config: CliConfig,
+        measure_level: bool,
+    ) -> Result<()> {
+        let bits = self.reader.spec().bits_per_sample;
+        let mode = cli_config.int_math;
+
+        for (i, (channel, segment)) in channels.iter_mut().zip(segments).enumerate() {
+            if cli_config.dry_wet < 1. {
+                let dry = self
+                    .read_channel(i, len, dry_offset, mode)
+                    .context("when reading from input file")?;
+                Self::mix(channel, &dry, cli_config.dry_wet, bits, mode);
+            }
+
+            if let Some(level) = cli_config.underlay {
+                let dry: Vec<S> = self
+                    .read_channel(i, len, dry_offset, mode)
+                    .context("when reading from input file")?;
+                for (w, d) in channel.iter_mut().zip(dry) {
+                    let value = d.to_f64() + level * SampleMath::to_f64(*w);
+                    *w = S::from_f64_clamped(value, bits, mode);
+                }
+            }
+
+            if segment.invert() {
+                for sample in channel.iter_mut() {
+                    *sample = S::from_f64_clamped(-SampleMath::to_f64(*sample), bits, mode);
                 }
-                channels.push(channel);
             }
         }
 
-        let mut samples: Vec<S> = vec![];
+        if cli_config.mid_glitch {
+            self.keep_mid(channels, len, dry_offset, mode)?;
+        }
 
-        for i in 0..channels[0].len() {
+        if cli_config.level_match > 0. {
+            self.match_level(channels, cli_config.level_match, measure_level, mode);
+        }
+        Ok(())
+    }
+
+    /// Rotates and orders `channels`, then interleaves their samples
+    fn interleave<S: SampleMath>(
+        &self,
+        mut channels: Vec<Vec<S>>,
+        cli_config: CliConfig,
+    ) -> Vec<S> {
+        let rotation = cli_config.rotate_channels as usize % channels.len();
+        channels.rotate_right(rotation);
+        if !self.order.is_empty() {
+            channels = self
+                .order
+                .iter()
+                .map(|&channel| channels[channel as usize].clone())
+                .collect();
+        }
+
+        let len = channels.iter().map(Vec::len).max().unwrap_or(0);
+        let mut samples: Vec<S> = Vec::with_capacity(len * channels.len());
+
+        for i in 0..len {
             for channel in &channels {
-                samples.push(channel[i].clone());
+                samples.push(channel.get(i).copied().unwrap_or(0.into()));
             }
         }
 
-        Ok(samples)
+        samples
+    }
+
+    /// Checks that the input has samples and that all of them can be read
+    pub fn validate(&mut self) -> Result<()> {
+        if self.duration() == 0 {
+            return Err(anyhow!("input has no samples"));
+        }
+        self.read_all()?;
+        Ok(())
+    }
+
+    /// Counts the input samples at full scale, before any mixing
+    pub fn clipped(&mut self) -> Result<usize> {
+        let spec = self.reader.spec();
+        let mut clipped = 0;
+
+        for reader in std::iter::once(&mut self.reader).chain(&mut self.appended) {
+            reader.seek(0).context("when reading from input file")?;
+            match spec.sample_format {
+                hound::SampleFormat::Int => {
+                    let min = -(1i64 << (spec.bits_per_sample - 1));
+                    for sample in reader.samples::<i32>() {
+                        let sample = sample.context("when reading from input file")? as i64;
+                        if sample == min || sample == -min - 1 {
+                            clipped += 1;
+                        }
+                    }
+                }
+                hound::SampleFormat::Float => {
+                    for sample in reader.samples::<f32>() {
+                        if sample.context("when reading from input file")?.abs() >= 1. {
+                            clipped += 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(clipped)
+    }
+
+    pub fn read_all(&mut self) -> Result<Vec<Vec<f64>>> {
+        let spec = self.reader.spec();
+        let mut channels = vec![vec![]; spec.channels as usize];
+
+        for reader in std::iter::once(&mut self.reader).chain(&mut self.appended) {
+            reader.seek(0).context("when reading from input file")?;
+            match spec.sample_format {
+                hound::SampleFormat::Int => {
+                    let scale = (1u64 << (spec.bits_per_sample - 1)) as f64;
+                    for (i, sample) in reader.samples::<i32>().enumerate() {
+                        let sample = sample.context("when reading from input file")?;
+                        channels[i % spec.channels as usize].push(sample as f64 / scale);
+                    }
+                }
+                hound::SampleFormat::Float => {
+                    for (i, sample) in reader.samples::<f32>().enumerate() {
+                        let sample = sample.context("when reading from input file")?;
+                        channels[i % spec.channels as usize].push(sample as f64);
+                    }
+                }
+            }
+        }
+
+        if self.matrix.is_empty() {
+            return Ok(channels);
+        }
+        Ok(self
+            .matrix
+            .iter()
+            .map(|row| {
+                let mut mixed = vec![0.; channels[0].len()];
+                for (channel, &weight) in channels.iter().zip(row) {
+                    for (sum, sample) in mixed.iter_mut().zip(channel) {
+                        *sum += sample * weight;
+                    }
+                }
+                mixed
+            })
+            .collect())
+    }
+
+    /// RMS level of `channel` in dBFS
+    fn dbfs<S: SampleMath>(&self, channel: &[S]) -> f64 {
+        let spec = self.reader.spec();
+        let full_scale = match spec.sample_format {
+            hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f64,
+            hound::SampleFormat::Float => 1.,
+        };
+        let power = channel
+            .iter()
+            .map(|sample| (sample.to_f64() / full_scale).powi(2))
+            .sum::<f64>()
+            / channel.len().max(1) as f64;
+        10. * power.log10()
+    }
+
+    /// Brings `channels` towards the level of the slices before them, measuring them if
+    /// `measure`, or else applying the gain of the last slice measured
+    fn match_level<S: SampleMath>(
+        &mut self,
+        channels: &mut [Vec<S>],
+        strength: f64,
+        measure: bool,
+        mode: IntMath,
+    ) {
+        const SMOOTHING: f64 = 0.3;
+        const MAX_GAIN: f64 = 4.;
+
+        if measure {
+            let (sum, count) = channels
+                .iter()
+                .flatten()
+                .fold((0., 0), |(sum, count), sample| {
+                    (sum + sample.to_f64().powi(2), count + 1)
+                });
+            let rms = (sum / count.max(1) as f64).sqrt();
+            if rms == 0. {
+                self.level_gain = 1.;
+                return;
+            }
+
+            let level = *self.level.get_or_insert(rms);
+            self.level_gain = (level / rms).powf(strength).clamp(1. / MAX_GAIN, MAX_GAIN);
+            self.level = Some(level + SMOOTHING * (rms - level));
+        }
+
+        let bits = self.reader.spec().bits_per_sample;
+        for sample in channels.iter_mut().flatten() {
+            *sample = S::from_f64_clamped(sample.to_f64() * self.level_gain, bits, mode);
+        }
+    }
+
+    /// Replaces the mid of processed stereo `channels` with the unprocessed mid at `offset`,
+    /// so that only their side is glitched
+    fn keep_mid<S: SampleMath>(
+        &mut self,
+        channels: &mut [Vec<S>],
+        segment_len: usize,
+        offset: u32,
+        mode: IntMath,
+    ) -> Result<()> {
+        let left: Vec<S> = self
+            .read_channel(0, segment_len, offset, mode)
+            .context("when reading from input file")?;
+        let right: Vec<S> = self
+            .read_channel(1, segment_len, offset, mode)
+            .context("when reading from input file")?;
+        let bits = self.reader.spec().bits_per_sample;
+        let [wet_left, wet_right] = channels else {
+            return Ok(());
+        };
+
+        for (((l, r), dry_l), dry_r) in wet_left.iter_mut().zip(wet_right).zip(left).zip(right) {
+            let mid = (dry_l.to_f64() + dry_r.to_f64()) / 2.;
+            let side = (SampleMath::to_f64(*l) - SampleMath::to_f64(*r)) / 2.;
+            *l = S::from_f64_clamped(mid + side, bits, mode);
+            *r = S::from_f64_clamped(mid - side, bits, mode);
+        }
+        Ok(())
+    }
+
+    fn mix<S: SampleMath>(wet: &mut [S], dry: &[S], dry_wet: f64, bits: u16, mode: IntMath) {
+        for (w, d) in wet.iter_mut().zip(dry) {
+            let value = w.to_f64() * dry_wet + d.to_f64() * (1. - dry_wet);
+            *w = S::from_f64_clamped(value, bits, mode);
+        }
+    }
+
+    fn read_channel<S: SampleMath>(
+        &mut self,
+        channel_idx: usize,
+        segment_len: usize,
+        segment_offset: u32,
+        mode: IntMath,
+    ) -> Result<Vec<S>, hound::Error> {
+        let Some(row) = self.matrix.get(channel_idx).cloned() else {
+            return self.read_segment(channel_idx, segment_len, segment_offset);
+        };
+        let mut mixed = vec![];
+        for (channel, weight) in row.into_iter().enumerate() {
+            let samples = self.read_segment::<S>(channel, segment_len, segment_offset)?;
+            mixed.resize(samples.len(), 0.);
+            for (sum, sample) in mixed.iter_mut().zip(samples) {
+                *sum += sample.to_f64() * weight;
+            }
+        }
+        let bits = self.reader.spec().bits_per_sample;
+        Ok(mixed
+            .into_iter()
+            .map(|sample| S::from_f64_clamped(sample, bits, mode))
+            .collect())
     }
 
     fn read_segment<S: hound::Sample>(
@@ -85,15 +684,27 @@ impl WavReader {
         segment_offset: u32,
     ) -> Result<Vec<S>, hound::Error> {
         let channel_count = self.reader.spec().channels as usize;
-        self.reader.seek(segment_offset)?;
+        let mut samples = Vec::with_capacity(segment_len);
+        let mut start = 0;
 
-        self.reader
-            .samples::<S>()
-            .take(channel_count * segment_len)
-            .enumerate()
-            .filter(|(j, _)| j % channel_count == channel_idx)
-            .map(|(_, s)| s)
-            .collect::<Result<Vec<S>, hound::Error>>()
+        for reader in std::iter::once(&mut self.reader).chain(&mut self.appended) {
+            let end = start + reader.duration();
+            let offset = segment_offset + samples.len() as u32;
+            if samples.len() < segment_len && (start..end).contains(&offset) {
+                reader.seek(offset - start)?;
+                for sample in reader
+                    .samples::<S>()
+                    .take(channel_count * (segment_len - samples.len()))
+                    .skip(channel_idx)
+                    .step_by(channel_count)
+                {
+                    samples.push(sample?);
+                }
+            }
+            start = end;
+        }
+
+        Ok(samples)
     }
 }
 
@@ -104,6 +715,36 @@ mod tests {
     use assert_fs::fixture::TempDir;
     use assert_fs::prelude::*;
 
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn wav_reader_read_mapped() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for i in 0..1000 {
+            writer.write_sample(i as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut buffered = WavReader::open_with(input.path(), ReadMode::Buffered(16)).unwrap();
+        let mut mapped = WavReader::open_with(input.path(), ReadMode::Mapped).unwrap();
+
+        for (channel, offset) in [(0, 0), (1, 0), (0, 250), (1, 480)] {
+            assert_eq!(
+                mapped.read_segment::<i32>(channel, 100, offset).unwrap(),
+                buffered.read_segment::<i32>(channel, 100, offset).unwrap()
+            );
+        }
+        assert_eq!(mapped.read_all().unwrap(), buffered.read_all().unwrap());
+
+        dir.close().unwrap();
+    }
+
     #[test]
     fn wav_reader_red_segment() {
         let dir = TempDir::new().unwrap();
@@ -129,6 +770,26 @@ mod tests {
         dir.close().unwrap();
     }
 
+    #[test]
+    fn wav_reader_read_all() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        input.write_binary(b"\x52\x49\x46\x46\x6c\x00\x00\x00\x57\x41\x56\x45\x66\x6d\x74\x20\x28\x00\x00\x00\
+                             \xfe\xff\x02\x00\x80\xbb\x00\x00\x00\x65\x04\x00\x06\x00\x18\x00\x16\x00\x18\x00\
+                             \x03\x00\x00\x00\x01\x00\x00\x00\x00\x00\x10\x00\x80\x00\x00\xaa\x00\x38\x9b\x71\
+                             \x64\x61\x74\x61\x30\x00\x00\x00\x01\x00\x00\xff\xff\xff\x02\x00\x00\xfe\xff\xff\
+                             \x03\x00\x00\xfd\xff\xff\x0b\x00\x00\xf5\xff\xff\x0c\x00\x00\xf4\xff\xff\x0d\x00\
+                             \x00\xf3\xff\xff\x15\x00\x00\xeb\xff\xff\x16\x00\x00\xea\xff\xff").unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let channels = reader.read_all().unwrap();
+
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0][3], 11. / 8388608.);
+        assert_eq!(channels[1][7], -22. / 8388608.);
+
+        dir.close().unwrap();
+    }
+
     #[test]
     fn wav_reader_read_1() {
         let dir = TempDir::new().unwrap();
@@ -142,30 +803,39 @@ mod tests {
         let mut reader = WavReader::open(input).unwrap();
         let mut slice_1 = SegmentSlice::new(
             vec![Segment::new(0, false, false), Segment::new(0, false, false)],
+            0,
             3,
             0.,
         );
         let mut slice_2 = SegmentSlice::new(
             vec![Segment::new(3, false, false), Segment::new(3, false, false)],
             3,
+            3,
             0.,
         );
         let mut slice_3 = SegmentSlice::new(
             vec![Segment::new(6, false, false), Segment::new(6, false, false)],
+            6,
             3,
             0.,
         );
 
         assert_eq!(
-            reader.read::<i32>(&mut slice_1).unwrap(),
+            reader
+                .read::<i32>(&mut slice_1, CliConfig::default())
+                .unwrap(),
             [1, -1, 2, -2, 3, -3]
         );
         assert_eq!(
-            reader.read::<i32>(&mut slice_2).unwrap(),
+            reader
+                .read::<i32>(&mut slice_2, CliConfig::default())
+                .unwrap(),
             [11, -11, 12, -12, 13, -13]
         );
         assert_eq!(
-            reader.read::<i32>(&mut slice_3).unwrap(),
+            reader
+                .read::<i32>(&mut slice_3, CliConfig::default())
+                .unwrap(),
             [21, -21, 22, -22]
         );
 
@@ -185,29 +855,41 @@ mod tests {
         let mut reader = WavReader::open(input).unwrap();
         let mut slice_1 = SegmentSlice::new(
             vec![Segment::new(0, false, true), Segment::new(0, false, true)],
+            0,
             3,
             0.,
         );
         let mut slice_2 = SegmentSlice::new(
             vec![Segment::new(3, false, true), Segment::new(3, false, true)],
             3,
+            3,
             0.,
         );
         let mut slice_3 = SegmentSlice::new(
             vec![Segment::new(6, false, true), Segment::new(6, false, true)],
+            6,
             3,
             0.,
         );
 
         assert_eq!(
-            reader.read::<i32>(&mut slice_1).unwrap(),
+            reader
+                .read::<i32>(&mut slice_1, CliConfig::default())
+                .unwrap(),
             [0, 0, 0, 0, 0, 0]
         );
         assert_eq!(
-            reader.read::<i32>(&mut slice_2).unwrap(),
+            reader
+                .read::<i32>(&mut slice_2, CliConfig::default())
+                .unwrap(),
             [0, 0, 0, 0, 0, 0]
         );
-        assert_eq!(reader.read::<i32>(&mut slice_3).unwrap(), [0, 0, 0, 0]);
+        assert_eq!(
+            reader
+                .read::<i32>(&mut slice_3, CliConfig::default())
+                .unwrap(),
+            [0, 0, 0, 0]
+        );
 
         dir.close().unwrap();
     }
@@ -225,30 +907,39 @@ mod tests {
         let mut reader = WavReader::open(input).unwrap();
         let mut slice_1 = SegmentSlice::new(
             vec![Segment::new(0, true, false), Segment::new(0, true, false)],
+            0,
             3,
             0.,
         );
         let mut slice_2 = SegmentSlice::new(
             vec![Segment::new(3, true, false), Segment::new(3, true, false)],
             3,
+            3,
             0.,
         );
         let mut slice_3 = SegmentSlice::new(
             vec![Segment::new(6, true, false), Segment::new(6, true, false)],
+            6,
             3,
             0.,
         );
 
         assert_eq!(
-            reader.read::<i32>(&mut slice_1).unwrap(),
+            reader
+                .read::<i32>(&mut slice_1, CliConfig::default())
+                .unwrap(),
             [3, -3, 2, -2, 1, -1]
         );
         assert_eq!(
-            reader.read::<i32>(&mut slice_2).unwrap(),
+            reader
+                .read::<i32>(&mut slice_2, CliConfig::default())
+                .unwrap(),
             [13, -13, 12, -12, 11, -11]
         );
         assert_eq!(
-            reader.read::<i32>(&mut slice_3).unwrap(),
+            reader
+                .read::<i32>(&mut slice_3, CliConfig::default())
+                .unwrap(),
             [22, -22, 21, -21]
         );
 
@@ -268,33 +959,899 @@ mod tests {
         let mut reader = WavReader::open(input).unwrap();
         let mut slice_1 = SegmentSlice::new(
             vec![Segment::new(0, false, false), Segment::new(3, false, false)],
+            0,
             3,
             0.,
         );
         let mut slice_2 = SegmentSlice::new(
             vec![Segment::new(3, false, false), Segment::new(0, false, false)],
             3,
+            3,
             0.,
         );
         let mut slice_3 = SegmentSlice::new(
             vec![Segment::new(6, false, false), Segment::new(6, false, false)],
+            6,
             3,
             0.,
         );
 
         assert_eq!(
-            reader.read::<i32>(&mut slice_1).unwrap(),
+            reader
+                .read::<i32>(&mut slice_1, CliConfig::default())
+                .unwrap(),
             [1, -11, 2, -12, 3, -13]
         );
         assert_eq!(
-            reader.read::<i32>(&mut slice_2).unwrap(),
+            reader
+                .read::<i32>(&mut slice_2, CliConfig::default())
+                .unwrap(),
             [11, -1, 12, -2, 13, -3]
         );
         assert_eq!(
-            reader.read::<i32>(&mut slice_3).unwrap(),
+            reader
+                .read::<i32>(&mut slice_3, CliConfig::default())
+                .unwrap(),
             [21, -21, 22, -22]
         );
 
         dir.close().unwrap();
     }
+
+    #[test]
+    fn wav_reader_read_dry() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        input.write_binary(b"\x52\x49\x46\x46\x6c\x00\x00\x00\x57\x41\x56\x45\x66\x6d\x74\x20\x28\x00\x00\x00\
+                             \xfe\xff\x02\x00\x80\xbb\x00\x00\x00\x65\x04\x00\x06\x00\x18\x00\x16\x00\x18\x00\
+                             \x03\x00\x00\x00\x01\x00\x00\x00\x00\x00\x10\x00\x80\x00\x00\xaa\x00\x38\x9b\x71\
+                             \x64\x61\x74\x61\x30\x00\x00\x00\x01\x00\x00\xff\xff\xff\x02\x00\x00\xfe\xff\xff\
+                             \x03\x00\x00\xfd\xff\xff\x0b\x00\x00\xf5\xff\xff\x0c\x00\x00\xf4\xff\xff\x0d\x00\
+                             \x00\xf3\xff\xff\x15\x00\x00\xeb\xff\xff\x16\x00\x00\xea\xff\xff").unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let mut slice = SegmentSlice::new(
+            vec![Segment::new(3, true, false), Segment::new(3, true, true)],
+            0,
+            3,
+            0.,
+        );
+        let cli_config = CliConfig {
+            dry_wet: 0.,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reader.read::<i32>(&mut slice, cli_config).unwrap(),
+            [1, -1, 2, -2, 3, -3]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_dry_wet() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        input.write_binary(b"\x52\x49\x46\x46\x6c\x00\x00\x00\x57\x41\x56\x45\x66\x6d\x74\x20\x28\x00\x00\x00\
+                             \xfe\xff\x02\x00\x80\xbb\x00\x00\x00\x65\x04\x00\x06\x00\x18\x00\x16\x00\x18\x00\
+                             \x03\x00\x00\x00\x01\x00\x00\x00\x00\x00\x10\x00\x80\x00\x00\xaa\x00\x38\x9b\x71\
+                             \x64\x61\x74\x61\x30\x00\x00\x00\x01\x00\x00\xff\xff\xff\x02\x00\x00\xfe\xff\xff\
+                             \x03\x00\x00\xfd\xff\xff\x0b\x00\x00\xf5\xff\xff\x0c\x00\x00\xf4\xff\xff\x0d\x00\
+                             \x00\xf3\xff\xff\x15\x00\x00\xeb\xff\xff\x16\x00\x00\xea\xff\xff").unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let mut slice = SegmentSlice::new(
+            vec![Segment::new(3, false, false), Segment::new(3, false, true)],
+            0,
+            3,
+            0.,
+        );
+        let cli_config = CliConfig {
+            dry_wet: 0.5,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reader.read::<i32>(&mut slice, cli_config).unwrap(),
+            [6, -1, 7, -1, 8, -2]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_rotate() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 3,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [1i16, 2, 3, 11, 12, 13] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let mut slice = SegmentSlice::new(vec![Segment::new(0, false, false); 3], 0, 2, 0.);
+        let cli_config = CliConfig {
+            rotate_channels: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reader.read::<i32>(&mut slice, cli_config).unwrap(),
+            [3, 1, 2, 13, 11, 12]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_clipped() {
+        let dir = TempDir::new().unwrap();
+        let clipped = dir.child("clipped.wav");
+        let clean = dir.child("clean.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(clipped.path(), spec).unwrap();
+        for sample in [32767i16, -32768, 32766, -32767, 0, 32767] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut writer = hound::WavWriter::create(clean.path(), spec).unwrap();
+        for sample in [32766i16, -32767, 0, 1000] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        assert_eq!(WavReader::open(clipped).unwrap().clipped().unwrap(), 3);
+        assert_eq!(WavReader::open(clean).unwrap().clipped().unwrap(), 0);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_order() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [1i16, 2, 11, 12] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let read = |order: Vec<u16>| {
+            let mut reader = WavReader::open(input.path())
+                .unwrap()
+                .with_order(order)
+                .unwrap();
+            let mut slice = SegmentSlice::new(vec![Segment::new(0, false, false); 2], 0, 2, 0.);
+            reader
+                .read::<i32>(&mut slice, CliConfig::default())
+                .unwrap()
+        };
+
+        assert_eq!(read(vec![1, 0]), [2, 1, 12, 11]);
+        assert_eq!(read(vec![0, 0]), [1, 1, 11, 11]);
+        assert_eq!(
+            WavReader::open(input.path())
+                .unwrap()
+                .with_order(vec![0, 2])
+                .err()
+                .unwrap()
+                .to_string(),
+            "ordered channel 2 is not in 0..2"
+        );
+        assert_eq!(
+            WavReader::open(input.path())
+                .unwrap()
+                .with_order(vec![0])
+                .err()
+                .unwrap()
+                .to_string(),
+            "channel order has 1 channels for 2 channels"
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_merge() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 4,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [10i16, 20, 30, 50, 32767, 32767, -32768, -32768] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input)
+            .unwrap()
+            .with_merge(vec![vec![0, 1], vec![2, 3]])
+            .unwrap();
+        let mut slice = SegmentSlice::new(vec![Segment::new(0, false, false); 2], 0, 2, 0.);
+
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(
+            reader
+                .read::<i32>(&mut slice, CliConfig::default())
+                .unwrap(),
+            [15, 40, 32767, -32768]
+        );
+        assert_eq!(reader.read_all().unwrap()[1], [40. / 32768., -1.]);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_matrix() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [10i16, 20, 30, -50, 32767, 1] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let read = |matrix: Vec<Vec<f64>>, channels| {
+            let mut reader = WavReader::open(input.path())
+                .unwrap()
+                .with_matrix(matrix)
+                .unwrap();
+            let mut slice =
+                SegmentSlice::new(vec![Segment::new(0, false, false); channels], 0, 3, 0.);
+            assert_eq!(reader.spec().channels, channels as u16);
+            reader
+                .read::<i32>(&mut slice, CliConfig::default())
+                .unwrap()
+        };
+
+        assert_eq!(
+            read(vec![vec![1., 0.], vec![0., 1.]], 2),
+            [10, 20, 30, -50, 32767, 1]
+        );
+        assert_eq!(read(vec![vec![1., 1.]], 1), [30, -20, 32767]);
+        assert_eq!(
+            WavReader::open(input.path())
+                .unwrap()
+                .with_matrix(vec![vec![1.]])
+                .err()
+                .unwrap()
+                .to_string(),
+            "matrix has 1 columns for 2 input channels"
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn read_matrix() {
+        let dir = TempDir::new().unwrap();
+        let matrix = dir.child("matrix.txt");
+        let invalid = dir.child("invalid.txt");
+        matrix.write_str("1, 0.5\n\n0 1\n").unwrap();
+        invalid.write_str("1 half").unwrap();
+
+        assert_eq!(
+            WavReader::read_matrix(matrix).unwrap(),
+            [vec![1., 0.5], vec![0., 1.]]
+        );
+        assert_eq!(
+            WavReader::read_matrix(invalid).unwrap_err().to_string(),
+            "when reading matrix value `half`"
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_merge_out_of_range() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        hound::WavWriter::create(input.path(), spec)
+            .unwrap()
+            .finalize()
+            .unwrap();
+
+        assert_eq!(
+            WavReader::open(input)
+                .unwrap()
+                .with_merge(vec![vec![0, 2]])
+                .err()
+                .unwrap()
+                .to_string(),
+            "merged channel 2 is not in 0..2"
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_concat() {
+        let dir = TempDir::new().unwrap();
+        let first = dir.child("first.wav");
+        let second = dir.child("second.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        for (input, value) in [(&first, 1000i16), (&second, -1000)] {
+            let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+            for _ in 0..200 {
+                writer.write_sample(value).unwrap();
+                writer.write_sample(value / 2).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+        let mut reader = WavReader::open(first)
+            .unwrap()
+            .with_concat(&[second], ReadMode::default())
+            .unwrap();
+        // The second input's segment swapped into a slot of the first input
+        let mut swapped = SegmentSlice::new(
+            vec![
+                Segment::new(200, false, false),
+                Segment::new(200, false, false),
+            ],
+            0,
+            100,
+            0.,
+        );
+        let mut across = SegmentSlice::new(
+            vec![
+                Segment::new(190, false, false),
+                Segment::new(190, false, false),
+            ],
+            190,
+            20,
+            0.,
+        );
+
+        assert_eq!(reader.config().duration, 400);
+        assert_eq!(
+            reader
+                .read::<i32>(&mut swapped, CliConfig::default())
+                .unwrap(),
+            [-1000, -500].repeat(100)
+        );
+        assert_eq!(
+            reader
+                .read::<i32>(&mut across, CliConfig::default())
+                .unwrap(),
+            [[1000, 500].repeat(10), [-1000, -500].repeat(10)].concat()
+        );
+        assert_eq!(reader.read_all().unwrap()[1].len(), 400);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_concat_spec_mismatch() {
+        let dir = TempDir::new().unwrap();
+        let first = dir.child("first.wav");
+        let second = dir.child("second.wav");
+        for (input, sample_rate) in [(&first, 48000), (&second, 44100)] {
+            let spec = hound::WavSpec {
+                channels: 1,
+                sample_rate,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            hound::WavWriter::create(input.path(), spec)
+                .unwrap()
+                .finalize()
+                .unwrap();
+        }
+
+        assert_eq!(
+            WavReader::open(first)
+                .unwrap()
+                .with_concat(&[second.path()], ReadMode::default())
+                .err()
+                .unwrap()
+                .to_string(),
+            format!(
+                "`{}` does not have the same spec as the input",
+                second.display()
+            )
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_validate() {
+        let dir = TempDir::new().unwrap();
+        let empty = dir.child("empty.wav");
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        hound::WavWriter::create(empty.path(), spec)
+            .unwrap()
+            .finalize()
+            .unwrap();
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        writer.write_sample(1i16).unwrap();
+        writer.finalize().unwrap();
+
+        assert_eq!(
+            WavReader::open(empty)
+                .unwrap()
+                .validate()
+                .unwrap_err()
+                .to_string(),
+            "input has no samples"
+        );
+        assert!(WavReader::open(input).unwrap().validate().is_ok());
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_level_match() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for i in 0..1600 {
+            let amplitude = if (i / 100) % 2 == 0 { 8000 } else { 1000 };
+            writer
+                .write_sample(if i % 2 == 0 { amplitude } else { -amplitude } as i16)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+        let rms_variance = |cli_config: CliConfig| {
+            let mut reader = WavReader::open(input.path()).unwrap();
+            let rms: Vec<f64> = (0..16)
+                .map(|i| {
+                    let mut slice =
+                        SegmentSlice::new(vec![Segment::new(i * 100, false, false)], 0, 100, 0.);
+                    let samples = reader.read::<i32>(&mut slice, cli_config).unwrap();
+                    (samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / 100.).sqrt()
+                })
+                .collect();
+            let mean = rms.iter().sum::<f64>() / rms.len() as f64;
+            rms.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / rms.len() as f64
+        };
+        let cli_config = CliConfig {
+            level_match: 0.8,
+            ..Default::default()
+        };
+
+        assert!(rms_variance(cli_config) < 0.25 * rms_variance(CliConfig::default()));
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_invert() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [1i16, 1, i16::MIN, i16::MIN, 5, 5] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let mut slice = SegmentSlice::new(
+            vec![
+                Segment::new(0, false, false),
+                Segment::new(0, false, false).with_invert(true),
+            ],
+            0,
+            3,
+            0.,
+        );
+
+        assert_eq!(
+            reader
+                .read::<i32>(&mut slice, CliConfig::default())
+                .unwrap(),
+            [1, -1, -32768, 32767, 5, -5]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_underlay() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [100i16, 200, 300, 400, 30000, 30000] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let cli_config = CliConfig {
+            underlay: Some(0.5),
+            ..Default::default()
+        };
+        let mut slice = SegmentSlice::new(vec![Segment::new(2, false, false)], 0, 2, 0.);
+        let mut clipped = SegmentSlice::new(vec![Segment::new(4, false, false)], 4, 2, 0.);
+
+        assert_eq!(
+            reader.read::<i32>(&mut slice, cli_config).unwrap(),
+            [100 + 150, 200 + 200]
+        );
+        assert_eq!(
+            reader.read::<i32>(&mut clipped, cli_config).unwrap(),
+            [32767, 32767]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_mid_glitch() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [100i16, 0, 200, 100, 300, 300, 400, 200] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let cli_config = CliConfig {
+            mid_glitch: true,
+            ..Default::default()
+        };
+        let mut slice = SegmentSlice::new(
+            vec![Segment::new(0, true, false), Segment::new(0, true, false)],
+            0,
+            4,
+            0.,
+        );
+        let samples = reader.read::<i32>(&mut slice, cli_config).unwrap();
+        let frames: Vec<_> = samples
+            .chunks(2)
+            .map(|frame| (frame[0], frame[1]))
+            .collect();
+
+        // Mid (L + R) is unchanged, side (L - R) is reversed
+        assert_eq!(
+            frames.iter().map(|(l, r)| l + r).collect::<Vec<_>>(),
+            [100, 300, 600, 600]
+        );
+        assert_eq!(
+            frames.iter().map(|(l, r)| l - r).collect::<Vec<_>>(),
+            [200, 0, 100, 100]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_reverse_window() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for _ in 0..4800 {
+            writer.write_sample(1000i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let cli_config = CliConfig {
+            reverse_window: true,
+            ..Default::default()
+        };
+        let mut reversed = SegmentSlice::new(vec![Segment::new(0, true, false)], 0, 4800, 0.);
+        let mut forward = SegmentSlice::new(vec![Segment::new(0, false, false)], 0, 4800, 0.);
+        let samples = reader.read::<i32>(&mut reversed, cli_config).unwrap();
+
+        assert_eq!(samples[0], 0);
+        assert!(samples[4799] < 10);
+        assert_eq!(samples[120], 500);
+        assert_eq!(samples[2400], 1000);
+        assert!(reader
+            .read::<i32>(&mut forward, cli_config)
+            .unwrap()
+            .iter()
+            .all(|&sample| sample == 1000));
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_reverse_above() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        // A quiet ramp at about -50 dBFS followed by a loud one at about -10 dBFS
+        for i in 0..100 {
+            writer.write_sample((i * 2) as i16).unwrap();
+        }
+        for i in 0..100 {
+            writer.write_sample((i * 200) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let cli_config = CliConfig {
+            reverse_above: Some(-30.),
+            ..Default::default()
+        };
+        let mut quiet = SegmentSlice::new(vec![Segment::new(0, true, false)], 0, 100, 0.);
+        let mut loud = SegmentSlice::new(vec![Segment::new(100, true, false)], 100, 100, 0.);
+
+        let quiet = reader.read::<i32>(&mut quiet, cli_config).unwrap();
+        let loud = reader.read::<i32>(&mut loud, cli_config).unwrap();
+        assert_eq!(quiet[..2], [0, 2]);
+        assert_eq!(loud[..2], [19800, 19600]);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_dc_block() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for i in 0..1000 {
+            writer
+                .write_sample(if i % 2 == 0 { 1500i16 } else { 500 })
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let cli_config = CliConfig {
+            segment_dc_block: true,
+            ..Default::default()
+        };
+        let mut slice = SegmentSlice::new(vec![Segment::new(0, true, false)], 0, 1000, 0.);
+        let samples = reader.read::<i32>(&mut slice, cli_config).unwrap();
+
+        assert_eq!(samples.iter().sum::<i32>(), 0);
+        assert_eq!(samples[..2], [-500, 500]);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_gain() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 4000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for sample in [16384i16, -1000, 16384, -1000] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+
+        let mut samples = vec![];
+        for (offset, silence) in [(0, false), (2, true)] {
+            let mut slice = SegmentSlice::new(
+                vec![Segment::new(offset, false, silence).with_gain(Some(4.))],
+                offset,
+                2,
+                0.,
+            );
+            samples.extend(
+                reader
+                    .read::<i32>(&mut slice, CliConfig::default())
+                    .unwrap(),
+            );
+        }
+        assert_eq!(samples, [32767, -4000, 0, 0]);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_tail() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 4000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for i in 0..12 {
+            writer.write_sample(i * 100i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let mut tail = |segment, cli_config| {
+            let slice = SegmentSlice::new(vec![segment], 4, 4, 0.);
+            reader.read_tail::<i32>(&slice, cli_config, 2).unwrap()
+        };
+        let dry_wet = CliConfig {
+            dry_wet: 0.5,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            tail(Segment::new(4, false, false), CliConfig::default()),
+            [800, 900]
+        );
+        assert_eq!(
+            tail(Segment::new(4, true, false), CliConfig::default()),
+            [300, 200]
+        );
+        assert_eq!(
+            tail(Segment::new(4, false, true), CliConfig::default()),
+            [0, 0]
+        );
+        assert_eq!(
+            tail(Segment::new(9, false, false), CliConfig::default()),
+            [0, 0]
+        );
+        assert_eq!(
+            tail(
+                Segment::new(4, false, false).with_invert(true),
+                CliConfig::default()
+            ),
+            [-800, -900]
+        );
+        assert_eq!(tail(Segment::new(4, true, false), dry_wet), [550, 550]);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_silence_ramp() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 4000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for _ in 0..12 {
+            writer.write_sample(100i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let mut read = |silence_ramp| {
+            let cli_config = CliConfig {
+                silence_ramp,
+                ..Default::default()
+            };
+            let mut samples = vec![];
+            for (offset, silence) in [(0, false), (4, true), (8, false)] {
+                let mut slice =
+                    SegmentSlice::new(vec![Segment::new(offset, false, silence)], offset, 4, 0.);
+                samples.extend(reader.read::<i32>(&mut slice, cli_config).unwrap());
+            }
+            samples
+        };
+        let largest_step = |samples: &[i32]| {
+            samples
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .max()
+                .unwrap()
+        };
+
+        let cut = read(false);
+        let ramped = read(true);
+        assert_eq!(cut, [100, 100, 100, 100, 0, 0, 0, 0, 100, 100, 100, 100]);
+        assert_eq!(
+            ramped,
+            [100, 100, 100, 100, 50, 0, 0, 50, 100, 100, 100, 100]
+        );
+        assert!(largest_step(&ramped) < largest_step(&cut));
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_reader_read_silence_fade() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.child("in.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 1000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+        for _ in 0..8 {
+            writer.write_sample(100i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut reader = WavReader::open(input).unwrap();
+        let cli_config = CliConfig {
+            silence_fade: 0.004,
+            ..Default::default()
+        };
+        let mut before = SegmentSlice::new(vec![Segment::new(0, false, false)], 0, 8, 0.)
+            .with_fades(vec![(false, true)]);
+        let mut after = SegmentSlice::new(vec![Segment::new(0, false, false)], 0, 8, 0.)
+            .with_fades(vec![(true, false)]);
+
+        assert_eq!(
+            reader.read::<i32>(&mut before, cli_config).unwrap(),
+            [100, 100, 100, 100, 75, 50, 25, 0]
+        );
+        assert_eq!(
+            reader.read::<i32>(&mut after, cli_config).unwrap(),
+            [0, 25, 50, 75, 100, 100, 100, 100]
+        );
+
+        dir.close().unwrap();
+    }
 }
@@ -1,36 +1,631 @@
-use anyhow::{Context, Result};
-use std::fs::{File, OpenOptions};
-use std::io::BufWriter;
-use std::path::Path;
+use crate::cli::{IntMath, PeakMode};
+use crate::loudness;
+use crate::sample_math::SampleMath;
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub trait SampleWriter {
+    fn write<S: SampleMath>(&mut self, samples: &[S]) -> Result<()>;
+    fn finalize(self) -> Result<()>;
+}
 
 pub struct WavWriter {
     writer: hound::WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    chunks: Vec<([u8; 4], Vec<u8>)>,
+    limit: Option<u64>,
+    written: u64,
+    loop_end: Option<u32>,
 }
 
 impl WavWriter {
-    pub fn create<P: AsRef<Path>>(path: P, spec: hound::WavSpec) -> Result<WavWriter> {
+    /// Creates the output file, truncating it if it exists and `force` is set and failing
+    /// otherwise
+    pub fn create<P: AsRef<Path>>(path: P, spec: hound::WavSpec, force: bool) -> Result<WavWriter> {
         let file = OpenOptions::new()
-            .create_new(true)
+            .create_new(!force)
+            .create(force)
+            .truncate(force)
             .write(true)
-            .open(path)
+            .open(path.as_ref())
             .context("when creating output file")?;
         let writer = hound::WavWriter::new(BufWriter::new(file), spec)
             .context("when creating output file")?;
-        Ok(WavWriter { writer })
+        Ok(WavWriter {
+            writer,
+            path: path.as_ref().to_path_buf(),
+            chunks: vec![],
+            limit: None,
+            written: 0,
+            loop_end: None,
+        })
+    }
+
+    /// Opens an existing file to append samples with the same spec to
+    pub fn append<P: AsRef<Path>>(path: P, spec: hound::WavSpec) -> Result<WavWriter> {
+        if Self::has_trailing_chunks(path.as_ref()).context("when opening output file")? {
+            return Err(anyhow!(
+                "output file has chunks after its samples and cannot be appended to"
+            ));
+        }
+        let writer = hound::WavWriter::append(path.as_ref()).context("when opening output file")?;
+        if writer.spec() != spec {
+            return Err(anyhow!(
+                "output file does not have the same spec as the input"
+            ));
+        }
+        // The size limit counts the samples already in the file
+        let written = writer.len() as u64 * spec.bits_per_sample.div_ceil(8) as u64;
+        Ok(WavWriter {
+            writer,
+            path: path.as_ref().to_path_buf(),
+            chunks: vec![],
+            limit: None,
+            written,
+            loop_end: None,
+        })
+    }
+
+    /// Whether any chunk follows the data chunk, as appended samples would overwrite it
+    fn has_trailing_chunks(path: &Path) -> io::Result<bool> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut offset = 12;
+        while offset + 8 <= len {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut header = [0; 8];
+            file.read_exact(&mut header)?;
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+            offset += 8 + size + size % 2;
+            if &header[..4] == b"data" {
+                return Ok(offset < len);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Frames written so far
+    pub fn duration(&self) -> u32 {
+        self.writer.duration()
     }
 
-    pub fn write<S: hound::Sample + Copy>(&mut self, samples: &[S]) -> Result<()> {
-        for sample in samples {
+    pub fn with_limit(mut self, limit: Option<u64>) -> WavWriter {
+        self.limit = limit;
+        self
+    }
+
+    pub fn with_info(mut self, info: Vec<([u8; 4], String)>) -> WavWriter {
+        if info.is_empty() {
+            return self;
+        }
+        let mut chunk = b"INFO".to_vec();
+        for (id, value) in info {
+            let len = value.len() + 1;
+            chunk.extend_from_slice(&id);
+            chunk.extend_from_slice(&(len as u32).to_le_bytes());
+            chunk.extend_from_slice(value.as_bytes());
+            chunk.push(0);
+            if len % 2 == 1 {
+                chunk.push(0);
+            }
+        }
+        self.chunks.push((*b"LIST", chunk));
+        self
+    }
+
+    pub fn with_loop(mut self, loop_points: Option<(u32, u32)>) -> WavWriter {
+        let Some((start, end)) = loop_points else {
+            return self;
+        };
+        let sample_period = 1_000_000_000 / self.writer.spec().sample_rate;
+        let mut chunk = vec![];
+        // manufacturer, product, sample period, MIDI unity note, pitch fraction,
+        // SMPTE format, SMPTE offset, loop count and sampler data size, followed
+        // by a single forward loop with inclusive end
+        for value in [
+            0,
+            0,
+            sample_period,
+            60,
+            0,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0,
+            start,
+            end,
+            0,
+            0,
+        ] {
+            chunk.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        self.chunks.push((*b"smpl", chunk));
+        self.loop_end = Some(end);
+        self
+    }
+
+    // hound does not write LIST or smpl chunks, so they are appended to the
+    // finalized file and the RIFF size is patched afterwards
+    fn append_chunks(path: &Path, chunks: &[([u8; 4], Vec<u8>)]) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        let mut len = file.seek(SeekFrom::End(0))?;
+        for (id, chunk) in chunks {
+            if len % 2 == 1 {
+                file.write_all(&[0])?;
+                len += 1;
+            }
+            file.write_all(id)?;
+            file.write_all(&(chunk.len() as u32).to_le_bytes())?;
+            file.write_all(chunk)?;
+            len += 8 + chunk.len() as u64;
+        }
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&(len as u32 - 8).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl SampleWriter for WavWriter {
+    fn write<S: SampleMath>(&mut self, samples: &[S]) -> Result<()> {
+        let spec = self.writer.spec();
+        let sample_size = spec.bits_per_sample.div_ceil(8) as u64;
+        let frame_size = sample_size * spec.channels as u64;
+        let count = match self.limit {
+            Some(limit) => samples.len().min(
+                (limit.saturating_sub(self.written) / frame_size * spec.channels as u64) as usize,
+            ),
+            None => samples.len(),
+        };
+        for sample in &samples[..count] {
             self.writer
                 .write_sample(*sample)
                 .context("when writing to output file")?;
         }
+        self.written += count as u64 * sample_size;
+        if count < samples.len() {
+            return Err(anyhow!(
+                "output reached the size limit of {} bytes",
+                self.limit.unwrap_or_default()
+            ));
+        }
         Ok(())
     }
 
-    pub fn finalize(self) -> Result<()> {
+    fn finalize(self) -> Result<()> {
+        let frames = self.writer.duration();
         self.writer
             .finalize()
-            .context("when finalizing output file")
+            .context("when finalizing output file")?;
+        // The output is only complete once written, so one that cannot hold its loop is
+        // removed rather than left without it
+        if let Some(end) = self.loop_end.filter(|&end| end >= frames) {
+            fs::remove_file(&self.path).context("when removing output file")?;
+            return Err(anyhow!(
+                "loop end {end} is past the last of the {frames} frames written"
+            ));
+        }
+        if !self.chunks.is_empty() {
+            Self::append_chunks(&self.path, &self.chunks)
+                .context("when writing metadata to output file")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct NullWriter {
+    samples: u64,
+}
+
+impl NullWriter {
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+}
+
+impl SampleWriter for NullWriter {
+    fn write<S: SampleMath>(&mut self, samples: &[S]) -> Result<()> {
+        self.samples += samples.len() as u64;
+        Ok(())
+    }
+
+    fn finalize(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct BufferWriter {
+    samples: Vec<f64>,
+}
+
+impl BufferWriter {
+    /// Picks the smallest integer depth that holds every sample without loss,
+    /// keeping `spec` if there is none smaller
+    pub fn lossless_spec(&self, spec: hound::WavSpec) -> hound::WavSpec {
+        for bits in [8, 16, 24] {
+            let fits = match spec.sample_format {
+                hound::SampleFormat::Int if bits < spec.bits_per_sample => {
+                    let step = (1i64 << (spec.bits_per_sample - bits)) as f64;
+                    self.samples.iter().all(|sample| sample % step == 0.)
+                }
+                hound::SampleFormat::Float => {
+                    let scale = (1i64 << (bits - 1)) as f64;
+                    self.samples.iter().all(|sample| {
+                        let value = sample * scale;
+                        value.fract() == 0. && (-scale..scale).contains(&value)
+                    })
+                }
+                _ => false,
+            };
+            if fits {
+                return hound::WavSpec {
+                    bits_per_sample: bits,
+                    sample_format: hound::SampleFormat::Int,
+                    ..spec
+                };
+            }
+        }
+        spec
+    }
+
+    /// Removes the frames of silence at the start and the end of the buffered samples
+    pub fn trim_silence(&mut self, channels: usize) {
+        let silent = |frame: &[f64]| frame.iter().all(|&sample| sample == 0.);
+        let frames: Vec<&[f64]> = self.samples.chunks(channels).collect();
+        let start = frames.iter().take_while(|frame| silent(frame)).count();
+        let end = frames.len()
+            - frames[start..]
+                .iter()
+                .rev()
+                .take_while(|frame| silent(frame))
+                .count();
+        self.samples = self.samples[start * channels..end * channels].to_vec();
+    }
+
+    /// Adds `other` scaled by `gain`, extending the buffered samples to its length
+    pub fn mix(&mut self, other: &BufferWriter, gain: f64) {
+        if self.samples.len() < other.samples.len() {
+            self.samples.resize(other.samples.len(), 0.);
+        }
+        for (sample, other) in self.samples.iter_mut().zip(&other.samples) {
+            *sample += other * gain;
+        }
+    }
+
+    /// Cuts the buffered samples to `len`, or pads them with silence to it
+    pub fn resize(&mut self, len: usize) {
+        self.samples.resize(len, 0.);
+    }
+
+    pub fn samples(&self) -> &[f64] {
+        &self.samples
+    }
+
+    /// Brings the buffered samples to `target` LUFS, see `loudness::normalize`
+    pub fn normalize_loudness(&mut self, target: f64, spec: hound::WavSpec, mode: PeakMode) {
+        let full_scale = match spec.sample_format {
+            hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f64,
+            hound::SampleFormat::Float => 1.,
+        };
+        let mut samples: Vec<f64> = self.samples.iter().map(|x| x / full_scale).collect();
+        loudness::normalize(
+            &mut samples,
+            spec.channels as usize,
+            spec.sample_rate,
+            target,
+            mode,
+        );
+        self.samples = samples.into_iter().map(|x| x * full_scale).collect();
+    }
+
+    /// Writes the buffered samples, converting them from `from` to `to` with overflow handled
+    /// as `mode` says
+    pub fn write_into<W: SampleWriter>(
+        self,
+        writer: &mut W,
+        from: hound::WavSpec,
+        to: hound::WavSpec,
+        mode: IntMath,
+    ) -> Result<()> {
+        let scale = match (from.sample_format, to.sample_format) {
+            (hound::SampleFormat::Int, hound::SampleFormat::Int) => {
+                1. / (1i64 << (from.bits_per_sample - to.bits_per_sample)) as f64
+            }
+            (hound::SampleFormat::Int, hound::SampleFormat::Float) => {
+                1. / (1i64 << (from.bits_per_sample - 1)) as f64
+            }
+            (hound::SampleFormat::Float, hound::SampleFormat::Int) => {
+                (1i64 << (to.bits_per_sample - 1)) as f64
+            }
+            (hound::SampleFormat::Float, hound::SampleFormat::Float) => 1.,
+        };
+        for block in self.samples.chunks(4096) {
+            match to.sample_format {
+                hound::SampleFormat::Int => {
+                    let block: Vec<i32> = block
+                        .iter()
+                        .map(|sample| {
+                            i32::from_f64_clamped(sample * scale, to.bits_per_sample, mode)
+                        })
+                        .collect();
+                    writer.write(&block)?;
+                }
+                hound::SampleFormat::Float => {
+                    let block: Vec<f32> =
+                        block.iter().map(|sample| (sample * scale) as f32).collect();
+                    writer.write(&block)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SampleWriter for BufferWriter {
+    fn write<S: SampleMath>(&mut self, samples: &[S]) -> Result<()> {
+        self.samples
+            .extend(samples.iter().map(|sample| sample.to_f64()));
+        Ok(())
+    }
+
+    fn finalize(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn wav_writer_info() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.child("out.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 8,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(output.path(), spec, false)
+            .unwrap()
+            .with_info(vec![
+                (*b"INAM", "Title".to_string()),
+                (*b"IART", "Me".to_string()),
+            ]);
+        writer.write(&[1i32, 2, 3]).unwrap();
+        writer.finalize().unwrap();
+
+        let bytes = std::fs::read(output.path()).unwrap();
+        assert_eq!(bytes.len(), 44 + 3 + 1 + 8 + 30);
+        assert_eq!(&bytes[4..8], &(bytes.len() as u32 - 8).to_le_bytes());
+        assert_eq!(
+            &bytes[48..],
+            b"LIST\x1e\0\0\0INFOINAM\x06\0\0\0Title\0IART\x03\0\0\0Me\0\0"
+        );
+        let mut reader = hound::WavReader::open(output.path()).unwrap();
+        assert_eq!(
+            reader
+                .samples::<i8>()
+                .map(|s| s.unwrap())
+                .collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_writer_loop() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.child("out.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 50000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(output.path(), spec, false)
+            .unwrap()
+            .with_info(vec![(*b"INAM", "Loop".to_string())])
+            .with_loop(Some((1, 3)));
+        writer.write(&[1i32, 2, 3, 4]).unwrap();
+        writer.finalize().unwrap();
+
+        let bytes = std::fs::read(output.path()).unwrap();
+        let smpl = 52 + 8 + 12 + 6;
+        let field =
+            |i: usize| u32::from_le_bytes(bytes[smpl + 8 + 4 * i..][..4].try_into().unwrap());
+        assert_eq!(&bytes[smpl..smpl + 8], b"smpl\x3c\0\0\0");
+        assert_eq!(bytes.len(), smpl + 8 + 60);
+        assert_eq!(&bytes[4..8], &(bytes.len() as u32 - 8).to_le_bytes());
+        assert_eq!((field(2), field(7)), (20000, 1));
+        assert_eq!((field(11), field(12)), (1, 3));
+
+        let mut writer = WavWriter::create(output.path(), spec, true)
+            .unwrap()
+            .with_loop(Some((1, 4)));
+        writer.write(&[1i32, 2, 3, 4]).unwrap();
+        assert_eq!(
+            writer.finalize().unwrap_err().to_string(),
+            "loop end 4 is past the last of the 4 frames written"
+        );
+        assert!(!output.exists());
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_writer_limit() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.child("out.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 24,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(output.path(), spec, false)
+            .unwrap()
+            .with_limit(Some(16));
+        writer.write(&[1i32, 2]).unwrap();
+
+        assert_eq!(
+            writer.write(&[3i32, 4, 5, 6]).unwrap_err().to_string(),
+            "output reached the size limit of 16 bytes"
+        );
+        writer.finalize().unwrap();
+        let mut reader = hound::WavReader::open(output.path()).unwrap();
+        assert_eq!(
+            reader
+                .samples::<i32>()
+                .map(|s| s.unwrap())
+                .collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn wav_writer_append() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.child("out.wav");
+        let tagged = dir.child("tagged.wav");
+        let int16 = spec(16, hound::SampleFormat::Int);
+        let mut writer = WavWriter::create(output.path(), int16, false).unwrap();
+        writer.write(&[1i32, 2]).unwrap();
+        writer.finalize().unwrap();
+        let mut writer = WavWriter::append(output.path(), int16).unwrap();
+        writer.write(&[3i32]).unwrap();
+        writer.finalize().unwrap();
+        let mut writer = WavWriter::append(output.path(), int16)
+            .unwrap()
+            .with_limit(Some(8));
+        assert_eq!(
+            writer.write(&[4i32, 5, 6]).unwrap_err().to_string(),
+            "output reached the size limit of 8 bytes"
+        );
+        writer.finalize().unwrap();
+        let mut writer = WavWriter::create(tagged.path(), int16, false)
+            .unwrap()
+            .with_info(vec![(*b"INAM", "Title".to_string())]);
+        writer.write(&[1i32]).unwrap();
+        writer.finalize().unwrap();
+
+        let mut reader = hound::WavReader::open(output.path()).unwrap();
+        assert_eq!(
+            reader
+                .samples::<i32>()
+                .map(|s| s.unwrap())
+                .collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            WavWriter::append(output.path(), spec(24, hound::SampleFormat::Int))
+                .err()
+                .unwrap()
+                .to_string(),
+            "output file does not have the same spec as the input"
+        );
+        assert_eq!(
+            WavWriter::append(tagged.path(), int16)
+                .err()
+                .unwrap()
+                .to_string(),
+            "output file has chunks after its samples and cannot be appended to"
+        );
+
+        dir.close().unwrap();
+    }
+
+    fn spec(bits_per_sample: u16, sample_format: hound::SampleFormat) -> hound::WavSpec {
+        hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+
+    #[test]
+    fn buffer_writer_lossless_int() {
+        let mut buffer = BufferWriter::default();
+        buffer.write(&[256i32, -512, 32767 * 256]).unwrap();
+        assert_eq!(
+            buffer.lossless_spec(spec(24, hound::SampleFormat::Int)),
+            spec(16, hound::SampleFormat::Int)
+        );
+
+        let mut converted = BufferWriter::default();
+        buffer
+            .write_into(
+                &mut converted,
+                spec(24, hound::SampleFormat::Int),
+                spec(16, hound::SampleFormat::Int),
+                IntMath::Saturate,
+            )
+            .unwrap();
+        assert_eq!(converted.samples, [1., -2., 32767.]);
+    }
+
+    #[test]
+    fn buffer_writer_lossless_keeps_depth() {
+        let mut buffer = BufferWriter::default();
+        buffer.write(&[256i32, 1]).unwrap();
+
+        assert_eq!(
+            buffer.lossless_spec(spec(24, hound::SampleFormat::Int)),
+            spec(24, hound::SampleFormat::Int)
+        );
+        assert_eq!(
+            buffer.lossless_spec(spec(16, hound::SampleFormat::Int)),
+            spec(16, hound::SampleFormat::Int)
+        );
+    }
+
+    #[test]
+    fn buffer_writer_lossless_float() {
+        let mut buffer = BufferWriter::default();
+        buffer.write(&[0.5f32, -1., 0.25]).unwrap();
+        assert_eq!(
+            buffer.lossless_spec(spec(32, hound::SampleFormat::Float)),
+            spec(8, hound::SampleFormat::Int)
+        );
+
+        buffer.write(&[0.1f32]).unwrap();
+        assert_eq!(
+            buffer.lossless_spec(spec(32, hound::SampleFormat::Float)),
+            spec(32, hound::SampleFormat::Float)
+        );
+    }
+
+    #[test]
+    fn buffer_writer_trim_silence() {
+        let mut buffer = BufferWriter::default();
+        buffer.write(&[0i32, 0, 0, 0, 0, 1, 2, 0, 0, 0]).unwrap();
+        buffer.trim_silence(2);
+        assert_eq!(buffer.samples, [0., 1., 2., 0.]);
+
+        let mut silent = BufferWriter::default();
+        silent.write(&[0i32; 4]).unwrap();
+        silent.trim_silence(2);
+        assert!(silent.samples.is_empty());
+    }
+
+    #[test]
+    fn null_writer_counts_samples() {
+        let mut writer = NullWriter::default();
+        writer.write(&[1i32, 2, 3]).unwrap();
+        writer.write(&[4f32]).unwrap();
+
+        assert_eq!(writer.samples(), 4);
     }
 }
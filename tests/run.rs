@@ -0,0 +1,1271 @@
+use assert_fs::fixture::{ChildPath, TempDir};
+use assert_fs::prelude::*;
+use std::process::{Command, Output};
+
+fn write_input(input: &ChildPath) {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+    for i in 0..48000 {
+        writer.write_sample((i % 1000) as i16).unwrap();
+        writer.write_sample(-((i % 1000) as i16)).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+fn wavglitch(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wavglitch"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn run_prints_defaults() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[input.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Using default value"));
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "\nDone\n");
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_quiet() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+    ]);
+
+    assert!(result.status.success());
+    assert!(result.stdout.is_empty());
+    assert!(result.stderr.is_empty());
+    assert!(output.exists());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_warns_on_unused_flag() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-a",
+        "4",
+    ]);
+
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr)
+        .contains("maximal swap range has no effect without swapping"));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_strict_rejects_unused_flag() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-a",
+        "4",
+        "--strict",
+    ]);
+
+    assert!(!result.status.success());
+    assert!(!output.exists());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_benchmark() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--benchmark",
+    ]);
+
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stdout).contains("Processed 96000 samples"));
+    assert!(!output.exists());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_start() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--start",
+        "0.01",
+    ]);
+    let mut reader = hound::WavReader::open(output.path()).unwrap();
+    let samples: Vec<i16> = reader.samples().take(4).map(Result::unwrap).collect();
+
+    assert!(result.status.success());
+    assert_eq!(reader.duration(), 47520);
+    assert_eq!(samples, [480, -480, 481, -481]);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_peaks() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let peaks = dir.child("peaks.json");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--peaks",
+        peaks.to_str().unwrap(),
+    ]);
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(peaks.path()).unwrap()).unwrap();
+
+    assert!(result.status.success());
+    assert_eq!(json["channels"], 2);
+    assert_eq!(json["peaks"][0].as_array().unwrap().len(), 94);
+    assert_eq!(json["peaks"][1].as_array().unwrap().len(), 94);
+
+    let max_peak = |json: &serde_json::Value| {
+        json["peaks"][0]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|peak| peak[1].as_f64().unwrap())
+            .fold(0., f64::max)
+    };
+    let loud = max_peak(&json);
+
+    // Metered after loudness normalization, which brings the peaks down
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-f",
+        "--lufs",
+        "-60",
+        "--peaks",
+        peaks.to_str().unwrap(),
+    ]);
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(peaks.path()).unwrap()).unwrap();
+
+    assert!(result.status.success());
+    assert_eq!(json["peaks"][0].as_array().unwrap().len(), 94);
+    assert!(max_peak(&json) < loud / 2.);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_max_output_size() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--max-output-size",
+        "0.01",
+    ]);
+
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr)
+        .contains("output reached the size limit of 10000 bytes"));
+    assert_eq!(hound::WavReader::open(output.path()).unwrap().len(), 5000);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_checksum() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    write_input(&input);
+    let checksum = |args: &[&str]| {
+        let result = wavglitch(
+            &[
+                &[input.to_str().unwrap(), "--benchmark", "--checksum"],
+                args,
+            ]
+            .concat(),
+        );
+        assert!(result.status.success());
+        String::from_utf8_lossy(&result.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("Checksum: ").map(str::to_string))
+            .unwrap()
+    };
+
+    assert_eq!(checksum(&["-r", "1"]), checksum(&["-r", "1"]));
+    assert_ne!(checksum(&["-r", "1"]), checksum(&["-r", "0"]));
+    assert_eq!(checksum(&[]).len(), 64);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_checksum_post_processing() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+    let checksum = |args: &[&str]| {
+        let result = wavglitch(
+            &[
+                &[
+                    input.to_str().unwrap(),
+                    "-o",
+                    output.to_str().unwrap(),
+                    "-f",
+                    "-q",
+                    "-r",
+                    "1",
+                    "--seed",
+                    "3",
+                    "--checksum",
+                ],
+                args,
+            ]
+            .concat(),
+        );
+        assert!(result.status.success());
+        String::from_utf8_lossy(&result.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("Checksum: ").map(str::to_string))
+            .unwrap()
+    };
+
+    let plain = checksum(&[]);
+    assert_ne!(checksum(&["--lufs", "-30"]), plain);
+    assert_ne!(checksum(&["--float"]), plain);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_render_tail() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let cut = dir.child("cut.wav");
+    let tail = dir.child("tail.wav");
+    write_input(&input);
+
+    for (output, extra) in [(&cut, &[][..]), (&tail, &["--render-tail"][..])] {
+        let args = [
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-q",
+            "--decorrelate",
+            "1",
+        ];
+        let result = wavglitch(&[&args[..], extra].concat());
+        assert!(result.status.success());
+    }
+
+    assert_eq!(
+        hound::WavReader::open(cut.path()).unwrap().duration(),
+        48000
+    );
+    let mut reader = hound::WavReader::open(tail.path()).unwrap();
+    assert!(reader.duration() > 48000);
+    let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+    assert!(samples[96000..].iter().any(|s| s.abs() > 1));
+    assert!(samples[samples.len() - 2048..].iter().all(|s| s.abs() <= 1));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_output_bit_depth_auto() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 48000,
+        bits_per_sample: 24,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+    for i in 0..48000 {
+        writer.write_sample((i % 1000 - 500) << 8).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--output-bit-depth-auto",
+    ]);
+    assert!(result.status.success());
+
+    let mut reader = hound::WavReader::open(output.path()).unwrap();
+    assert_eq!(reader.spec().bits_per_sample, 16);
+    let samples: Vec<i32> = reader.samples::<i32>().map(Result::unwrap).collect();
+    assert_eq!(samples.len(), 48000);
+    assert!(samples.contains(&-500) && samples.contains(&499));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_segment_layout_cache() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let cache = dir.child("layout.json");
+    write_input(&input);
+    let output = |name: &str| {
+        let output = dir.child(name);
+        let result = wavglitch(&[
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-q",
+            "-l",
+            "1/64",
+            "-w",
+            "0.5",
+            "-r",
+            "0.5",
+            "--segment-layout-cache",
+            cache.to_str().unwrap(),
+        ]);
+        assert!(result.status.success());
+        std::fs::read(output.path()).unwrap()
+    };
+
+    let first = output("first.wav");
+    assert!(cache.exists());
+    assert_eq!(output("second.wav"), first);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_validate_only() {
+    let dir = TempDir::new().unwrap();
+    let good = dir.child("good.wav");
+    let bad = dir.child("bad.wav");
+    write_input(&good);
+    bad.write_str("not a wav file").unwrap();
+
+    let result = wavglitch(&[good.to_str().unwrap(), "--validate-only"]);
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stdout).ends_with("good.wav: OK\n"));
+
+    let result = wavglitch(&[bad.to_str().unwrap(), "--validate-only"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("when opening input file"));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_output_dir() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output_dir = dir.child("processed/stems");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "--output-dir",
+        output_dir.to_str().unwrap(),
+        "-q",
+    ]);
+
+    assert!(result.status.success());
+    assert!(output_dir.child("in-glitch.wav").exists());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_lufs() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--lufs",
+        "-20",
+    ]);
+    assert!(result.status.success());
+
+    let mut reader = hound::WavReader::open(output.path()).unwrap();
+    let samples: Vec<f64> = reader
+        .samples::<i32>()
+        .map(|sample| sample.unwrap() as f64 / 32768.)
+        .collect();
+    let loudness = wavglitch::loudness::integrated(&samples, 2, 48000);
+    assert!((loudness + 20.).abs() < 0.1, "{loudness}");
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_interactive_without_terminal() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+    output.write_str("existing").unwrap();
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "--interactive",
+    ]);
+
+    assert!(!result.status.success());
+    output.assert("existing");
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_analysis() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let analysis = dir.child("analysis.json");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "-r",
+        "1",
+        "--analysis",
+        analysis.to_str().unwrap(),
+    ]);
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(analysis.path()).unwrap()).unwrap();
+
+    assert!(result.status.success());
+    let segments = json.as_array().unwrap();
+    assert_eq!(segments.len(), 7);
+    for (i, segment) in segments.iter().enumerate() {
+        assert_eq!(segment["index"], i);
+        assert_eq!(segment["offset"], i * 7200);
+        assert_eq!(segment["length"], 7200);
+        assert_eq!(segment["sources"].as_array().unwrap().len(), 2);
+        assert!(segment["rms"].as_f64().unwrap() > 0.);
+        assert!(segment["peak"].as_f64().unwrap() >= segment["rms"].as_f64().unwrap());
+        assert_eq!(segment["effects"], serde_json::json!(["reverse"]));
+    }
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_transient_segments_length() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+    let clicks = [
+        1500, 3100, 7000, 8200, 13000, 19500, 21000, 26800, 30500, 31440,
+    ];
+    for i in 0..32000 {
+        let click = clicks.iter().any(|&click| (click..click + 40).contains(&i));
+        let sample = if click { 20000 } else { (i % 50) as i16 - 25 };
+        writer.write_sample(sample).unwrap();
+        writer.write_sample(-sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    for seed in ["1", "13", "14", "28"] {
+        for separately in [&[][..], &["-c"]] {
+            let result = wavglitch(
+                &[
+                    &[
+                        input.to_str().unwrap(),
+                        "-o",
+                        output.to_str().unwrap(),
+                        "-f",
+                        "-q",
+                        "-S",
+                        seed,
+                        "--transient-segments",
+                        "-w",
+                        "0.9",
+                        "-p",
+                        "0.9",
+                    ],
+                    separately,
+                ]
+                .concat(),
+            );
+            assert!(result.status.success());
+            let reader = hound::WavReader::open(output.path()).unwrap();
+
+            assert_eq!(reader.duration(), 32000);
+        }
+    }
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_channel_delays() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--channel-delays",
+        "0,100",
+    ]);
+    assert!(result.status.success());
+
+    let mut reader = hound::WavReader::open(output.path()).unwrap();
+    let samples: Vec<i32> = reader.samples::<i32>().map(Result::unwrap).collect();
+    let (left, right): (Vec<i32>, Vec<i32>) =
+        samples.chunks(2).map(|frame| (frame[0], frame[1])).unzip();
+    assert_eq!(left.len(), 48100);
+    assert!(right[..100].iter().all(|&sample| sample == 0));
+    for i in 0..48000 {
+        assert_eq!(left[i], i as i32 % 1000);
+        assert_eq!(right[i + 100], -(i as i32 % 1000));
+    }
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        dir.child("mismatch.wav").to_str().unwrap(),
+        "--channel-delays",
+        "0,100,200",
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr)
+        .contains("channel delays list has 3 values for 2 channels"));
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        dir.child("long.wav").to_str().unwrap(),
+        "--channel-delays",
+        "0,4000000000",
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr)
+        .contains("channel delay of 4000000000 samples is longer than 10 s"));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_trim_output_silence() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+    for i in 0..48000 {
+        let sample = if i < 4800 { 0 } else { 1 + (i % 1000) as i16 };
+        writer.write_sample(sample).unwrap();
+        writer.write_sample(-sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--trim-output-silence",
+    ]);
+    assert!(result.status.success());
+
+    let mut reader = hound::WavReader::open(output.path()).unwrap();
+    assert_eq!(reader.duration(), 48000 - 4800);
+    assert_ne!(reader.samples::<i16>().next().unwrap().unwrap(), 0);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_max_channels() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let spec = hound::WavSpec {
+        channels: 40,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+    for _ in 0..40 * 100 {
+        writer.write_sample(0i16).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr)
+        .contains("input has 40 channels, more than the maximum of 32"));
+    assert!(!output.exists());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_timestamp() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output_dir = dir.child("out");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "--output-dir",
+        output_dir.to_str().unwrap(),
+        "--timestamp",
+        "-q",
+    ]);
+    assert!(result.status.success());
+
+    let names: Vec<String> = std::fs::read_dir(output_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names.len(), 1);
+    let timestamp = names[0]
+        .strip_prefix("in-glitch-")
+        .and_then(|name| name.strip_suffix("Z.wav"))
+        .unwrap();
+    assert_eq!(timestamp.len(), 15);
+    assert!(timestamp.chars().enumerate().all(|(i, c)| if i == 8 {
+        c == 'T'
+    } else {
+        c.is_ascii_digit()
+    }));
+    hound::WavReader::open(output_dir.child(&names[0]).path()).unwrap();
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_parallel_chain() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let clean = dir.child("clean.wav");
+    let reversed = dir.child("reversed.wav");
+    let mixed = dir.child("mixed.wav");
+    write_input(&input);
+
+    let read = |output: &ChildPath| -> Vec<i32> {
+        hound::WavReader::open(output.path())
+            .unwrap()
+            .samples::<i32>()
+            .map(Result::unwrap)
+            .collect()
+    };
+
+    // The chain starts where the main one does
+    for start in ["0", "0.25"] {
+        for (output, args) in [
+            (&clean, vec![]),
+            (&reversed, vec!["-r", "1"]),
+            (&mixed, vec!["--parallel-chain", "-r 1"]),
+        ] {
+            let mut all = vec![
+                input.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "-q",
+                "-f",
+                "--start",
+                start,
+            ];
+            all.extend(args);
+            assert!(wavglitch(&all).status.success());
+        }
+
+        let (clean, reversed, mixed) = (read(&clean), read(&reversed), read(&mixed));
+        assert_eq!(mixed.len(), clean.len());
+        assert!(mixed
+            .iter()
+            .zip(clean.iter().zip(&reversed))
+            .all(|(mixed, (clean, reversed))| (2 * mixed - clean - reversed).abs() <= 1));
+    }
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_write_intermediate() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let intermediate = dir.child("intermediate.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "-r",
+        "0.5",
+        "-s",
+        "0.5",
+        "--write-intermediate",
+        intermediate.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+
+    let read = |path: &ChildPath| -> Vec<i32> {
+        hound::WavReader::open(path.path())
+            .unwrap()
+            .samples::<i32>()
+            .map(Result::unwrap)
+            .collect()
+    };
+    assert_eq!(read(&intermediate), read(&input));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_auto_tempo() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let analysis = dir.child("analysis.json");
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    // A click every half second, 120 BPM
+    let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+    for i in 0..192000 {
+        let sample = if i % 24000 < 240 { 16384 } else { 0 };
+        writer.write_sample(sample as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--auto-tempo",
+        "--analysis",
+        analysis.to_str().unwrap(),
+    ]);
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(analysis.path()).unwrap()).unwrap();
+
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&result.stdout),
+        "Detected tempo: 120.0 BPM\n"
+    );
+    assert_eq!(json[0]["length"], 6000);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_dc_report() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    // An offset of a tenth of full scale in the first channel
+    let mut writer = hound::WavWriter::create(input.path(), spec).unwrap();
+    for i in 0..48000 {
+        writer.write_sample(3277 + (i % 2) * 1000 - 500).unwrap();
+        writer.write_sample((i % 2) * 1000 - 500).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--dc-report",
+    ]);
+
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&result.stdout),
+        "DC offset: 0.1000, 0.0000\n"
+    );
+    assert!(String::from_utf8_lossy(&result.stderr).contains("DC offset above -40 dBFS"));
+
+    // Metered after loudness normalization brings the offset down with the level
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "-f",
+        "--dc-report",
+        "--lufs",
+        "-60",
+    ]);
+    let offset: f64 = String::from_utf8_lossy(&result.stdout)
+        .trim()
+        .strip_prefix("DC offset: ")
+        .and_then(|report| report.split(", ").next())
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    assert!(result.status.success());
+    assert!(offset > 0. && offset < 0.01);
+    assert!(!String::from_utf8_lossy(&result.stderr).contains("DC offset above -40 dBFS"));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_log() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let log = dir.child("runs.jsonl");
+    write_input(&input);
+
+    for (output, reverse, seed) in [
+        ("first.wav", "1", &["-S", "7"][..]),
+        ("second.wav", "0", &[]),
+    ] {
+        let output = dir.child(output);
+        let args = [
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-q",
+            "-r",
+            reverse,
+            "--log",
+            log.to_str().unwrap(),
+        ];
+        let result = wavglitch(&[&args[..], seed].concat());
+        assert!(result.status.success());
+    }
+    let log = std::fs::read_to_string(log.path()).unwrap();
+    let records: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(records.len(), 2);
+    assert!(records[0]["output"]
+        .as_str()
+        .unwrap()
+        .ends_with("first.wav"));
+    assert_eq!(records[0]["config"]["prob_reverse"], 1.);
+    assert_eq!(records[0]["seed"], 7);
+    assert!(records[1]["seed"].is_u64());
+    assert_eq!(records[1]["config"]["prob_reverse"], 0.);
+    assert_eq!(records[1]["frames"], 48000);
+    assert_eq!(records[1]["warnings"], serde_json::json!([]));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_append() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    for reverse in ["1", "0"] {
+        let result = wavglitch(&[
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-q",
+            "-r",
+            reverse,
+            "--append",
+        ]);
+        assert!(result.status.success());
+    }
+    let reader = hound::WavReader::open(output.path()).unwrap();
+
+    assert_eq!(reader.duration(), 96000);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_seed() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    write_input(&input);
+    let run = |args: &[&str]| {
+        wavglitch(
+            &[
+                &[input.to_str().unwrap(), "--benchmark", "--checksum"],
+                args,
+            ]
+            .concat(),
+        )
+    };
+    let checksum_of = |result: &Output| {
+        assert!(result.status.success());
+        String::from_utf8_lossy(&result.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("Checksum: ").map(str::to_string))
+            .unwrap()
+    };
+    let checksum = |args: &[&str]| checksum_of(&run(args));
+    let effects = ["-r", "0.5", "-p", "0.5", "--freeze", "0.5"];
+
+    assert_eq!(
+        checksum(&[&effects[..], &["-S", "42"]].concat()),
+        checksum(&[&effects[..], &["--seed", "42"]].concat())
+    );
+    assert_ne!(
+        checksum(&[&effects[..], &["-S", "42"]].concat()),
+        checksum(&[&effects[..], &["-S", "43"]].concat())
+    );
+    assert!(String::from_utf8_lossy(&run(&effects).stderr).contains("Using random seed"));
+
+    let result = run(&[&effects[..], &["--seed-format", "hex"]].concat());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let seed = stderr
+        .split_once("Using random seed ")
+        .and_then(|(_, rest)| rest.split_once(','))
+        .map(|(seed, _)| seed)
+        .unwrap();
+
+    assert!(seed.starts_with("0x"));
+    assert_eq!(
+        checksum_of(&result),
+        checksum(&[&effects[..], &["-S", seed]].concat())
+    );
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_seed_sweep() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let sweep = dir.child("sweep.wav");
+    let single = dir.child("single.wav");
+    write_input(&input);
+    let effects = ["-q", "-r", "0.5", "-p", "0.5", "--freeze", "0.5"];
+
+    let result = wavglitch(
+        &[
+            &[
+                input.to_str().unwrap(),
+                "-o",
+                sweep.to_str().unwrap(),
+                "--seed-sweep",
+                "3:5:0.25",
+            ],
+            &effects[..],
+        ]
+        .concat(),
+    );
+    assert!(result.status.success());
+    let result = wavglitch(
+        &[
+            &[
+                input.to_str().unwrap(),
+                "-o",
+                single.to_str().unwrap(),
+                "-S",
+                "4",
+            ],
+            &effects[..],
+        ]
+        .concat(),
+    );
+    assert!(result.status.success());
+
+    let read = |output: &ChildPath| -> Vec<i32> {
+        hound::WavReader::open(output.path())
+            .unwrap()
+            .samples::<i32>()
+            .map(Result::unwrap)
+            .collect()
+    };
+    let (sweep, single) = (read(&sweep), read(&single));
+    // Three previews of 12000 frames with two gaps of 24000 frames
+    assert_eq!(sweep.len(), 2 * (3 * 12000 + 2 * 24000));
+    // The second preview is the start of what its seed renders alone
+    assert_eq!(sweep[2 * 36000..2 * 48000], single[..2 * 12000]);
+    assert!(sweep[2 * 12000..2 * 36000]
+        .iter()
+        .all(|&sample| sample == 0));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_ensure_change() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let unchanged = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "-r",
+        "0",
+        "--ensure-change",
+    ]);
+    let changed = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "-r",
+        "0.5",
+        "--ensure-change",
+    ]);
+
+    assert!(!unchanged.status.success());
+    assert!(String::from_utf8_lossy(&unchanged.stderr)
+        .contains("layout left the input unchanged in 8 attempts"));
+    assert!(changed.status.success());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_float() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+        "-q",
+        "--float",
+    ]);
+    assert!(result.status.success());
+
+    let mut reader = hound::WavReader::open(output.path()).unwrap();
+    assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+    assert_eq!(reader.spec().bits_per_sample, 32);
+    let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+    assert_eq!(samples.len(), 96000);
+    assert_eq!(samples[2 * 999], 999. / 32768.);
+    assert_eq!(samples[2 * 999 + 1], -999. / 32768.);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_prob_matrix() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let matrix = dir.child("matrix.txt");
+    let cache = dir.child("layout.json");
+    write_input(&input);
+    matrix
+        .write_str("0 0 0 0 0 0 0 0 0\n0 0 1 0 0 0 0 0 0\n")
+        .unwrap();
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        dir.child("out.wav").to_str().unwrap(),
+        "-q",
+        "--prob-matrix",
+        matrix.to_str().unwrap(),
+        "--segment-layout-cache",
+        cache.to_str().unwrap(),
+    ]);
+    assert!(result.status.success());
+    let layout: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(cache.path()).unwrap()).unwrap();
+    let reversed = |channel: usize| {
+        layout["segments"][channel]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|segment| segment["reverse"] == true)
+            .count()
+    };
+    assert_eq!(reversed(0), 0);
+    assert_eq!(reversed(1), layout["slots"].as_array().unwrap().len());
+
+    let mono = dir.child("mono.txt");
+    mono.write_str("0 0 1 0 0 0 0 0 0\n").unwrap();
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        dir.child("mono.wav").to_str().unwrap(),
+        "-q",
+        "--prob-matrix",
+        mono.to_str().unwrap(),
+    ]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr)
+        .contains("probability matrix has 1 rows for 2 channels"));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_base64() {
+    use base64::prelude::*;
+
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    write_input(&input);
+
+    let result = wavglitch(&[
+        input.to_str().unwrap(),
+        "-o",
+        dir.child("out.wav").to_str().unwrap(),
+        "--base64",
+        "--checksum",
+        "--coverage",
+    ]);
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Checksum: "));
+    let bytes = BASE64_STANDARD
+        .decode(String::from_utf8_lossy(&result.stdout).trim())
+        .unwrap();
+    let reader = hound::WavReader::new(std::io::Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.len(), 96000);
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn run_force() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.child("in.wav");
+    let output = dir.child("out.wav");
+    write_input(&input);
+    let run = |args: &[&str]| {
+        wavglitch(
+            &[
+                &[
+                    input.to_str().unwrap(),
+                    "-o",
+                    output.to_str().unwrap(),
+                    "-q",
+                ],
+                args,
+            ]
+            .concat(),
+        )
+    };
+
+    assert!(run(&[]).status.success());
+    let result = run(&["-r", "1"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("when creating output file"));
+    let original = std::fs::read(output.path()).unwrap();
+    assert!(run(&["-r", "1", "-f"]).status.success());
+    assert_ne!(std::fs::read(output.path()).unwrap(), original);
+    assert!(run(&["--force", "--lufs", "-14"]).status.success());
+
+    dir.close().unwrap();
+}